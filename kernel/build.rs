@@ -1,3 +1,15 @@
+use std::{env, path::PathBuf};
+
 fn main() {
     println!("cargo:rerun-if-changed=link.ld");
+
+    // Embed the user test program if it has been built, see the `user` target in the Makefile
+    let image = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
+        .join("../user/hello/target/aarch64-unknown-none/release/hello");
+    println!("cargo:rerun-if-changed={}", image.display());
+    println!("cargo::rustc-check-cfg=cfg(user_image)");
+    if image.exists() {
+        println!("cargo:rustc-cfg=user_image");
+        println!("cargo:rustc-env=USER_IMAGE={}", image.display());
+    }
 }
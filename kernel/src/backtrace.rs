@@ -0,0 +1,93 @@
+use crate::{cpu, memory::map, symbols::Symbolized};
+use core::{arch::asm, fmt::Display};
+
+const MAX_FRAMES: usize = 32;
+
+/// Frame record pushed by every function prologue, x29 points at it
+#[repr(C)]
+struct FrameRecord {
+    fp: usize,
+    lr: usize,
+}
+
+/// Call chain recovered by walking the x29 frame pointer chain
+pub struct Backtrace {
+    pc: Option<usize>,
+    fp: usize,
+}
+
+impl Backtrace {
+    /// Backtrace of the caller
+    #[inline(always)]
+    pub fn current() -> Self {
+        let fp: usize;
+        unsafe { asm!("mov {}, x29", out(reg) fp, options(nomem, nostack)) };
+
+        Self { pc: None, fp }
+    }
+
+    /// Backtrace of code interrupted by an exception
+    pub fn from_exception(pc: u64, fp: u64) -> Self {
+        Self {
+            pc: Some(pc as usize),
+            fp: fp as usize,
+        }
+    }
+}
+
+/// Bounds of the stack frame records may live in
+fn stack_bounds() -> (usize, usize) {
+    match cpu::id() {
+        0 => (
+            map::boot_core_stack_start(),
+            map::boot_core_stack_end_exclusive(),
+        ),
+        core => (
+            map::secondary_stack_start(core),
+            map::secondary_stack_end_exclusive(core),
+        ),
+    }
+}
+
+impl Display for Backtrace {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "Backtrace:")?;
+
+        let mut depth = 0;
+        if let Some(pc) = self.pc {
+            writeln!(f, "    #{:<2} {}", depth, Symbolized(pc))?;
+            depth += 1;
+        }
+
+        let (start, end) = stack_bounds();
+        let mut fp = self.fp;
+        while depth < MAX_FRAMES {
+            // Anything outside the stack means the chain ended or is corrupted. Reading it could
+            // fault again
+            if fp < start || fp > end - size_of::<FrameRecord>() || !fp.is_multiple_of(8) {
+                break;
+            }
+
+            let record = unsafe { &*(fp as *const FrameRecord) };
+            if record.lr == 0 {
+                break;
+            }
+
+            // lr is the return address, the call is the instruction before it
+            writeln!(f, "    #{:<2} {}", depth, Symbolized(record.lr - 4))?;
+            depth += 1;
+
+            // Callers' frames are always higher up the stack
+            if record.fp <= fp {
+                break;
+            }
+            fp = record.fp;
+        }
+
+        if depth == MAX_FRAMES {
+            writeln!(f, "    ...")?;
+        }
+
+        Ok(())
+    }
+}
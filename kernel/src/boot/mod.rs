@@ -1,23 +1,43 @@
-use crate::kernel_init;
-use aarch64_cpu::registers::*;
+use crate::{
+    exception::{current_el, PrivilegeLevel},
+    kernel_init, secondary_init,
+};
+use aarch64_cpu::{asm::barrier, registers::*};
 use core::arch::{asm, global_asm};
 
 global_asm!(include_str!("boot.S"));
 
 #[no_mangle]
 pub unsafe extern "C" fn __start_rust(sp_addr: u64) -> ! {
-    prepare_jump_to_el1(sp_addr);
+    prepare_jump_to_el1(sp_addr, kernel_init);
 
     // Jumps to address in ELR
     asm!("eret", options(noreturn))
 }
 
-unsafe fn prepare_jump_to_el1(sp_addr: u64) {
-    CNTHCTL_EL2.write(CNTHCTL_EL2::EL1PCEN::SET + CNTHCTL_EL2::EL1PCTEN::SET);
-    CNTVOFF_EL2.set(0);
+#[no_mangle]
+pub unsafe extern "C" fn __start_rust_secondary(sp_addr: u64) -> ! {
+    // PSCI starts cores at the EL that asked for them
+    if let PrivilegeLevel::Kernel = current_el() {
+        enable_fp();
+        barrier::isb(barrier::SY);
+        secondary_init()
+    }
+
+    prepare_jump_to_el1(sp_addr, secondary_init);
+    asm!("eret", options(noreturn))
+}
 
+unsafe fn enable_fp() {
     CPACR_EL1
         .write(CPACR_EL1::FPEN::TrapNothing + CPACR_EL1::TTA::NoTrap + CPACR_EL1::ZEN::TrapNothing);
+}
+
+unsafe fn prepare_jump_to_el1(sp_addr: u64, entry: unsafe fn() -> !) {
+    CNTHCTL_EL2.write(CNTHCTL_EL2::EL1PCEN::SET + CNTHCTL_EL2::EL1PCTEN::SET);
+    CNTVOFF_EL2.set(0);
+
+    enable_fp();
     HCR_EL2.write(HCR_EL2::RW::EL1IsAarch64);
 
     SPSR_EL2.write(
@@ -28,6 +48,6 @@ unsafe fn prepare_jump_to_el1(sp_addr: u64) {
             + SPSR_EL2::M::EL1h,
     );
 
-    ELR_EL2.set(kernel_init as *const () as u64);
+    ELR_EL2.set(entry as *const () as u64);
     SP_EL1.set(sp_addr);
 }
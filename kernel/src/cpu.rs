@@ -0,0 +1,30 @@
+use crate::exception;
+use aarch64_cpu::{
+    asm,
+    registers::{Readable, MPIDR_EL1},
+};
+
+/// Index of the core this is running on
+pub fn id() -> usize {
+    (MPIDR_EL1.get() & 0xFF) as usize
+}
+
+/// Park the core until an interrupt becomes pending. This returns even if IRQs are masked
+pub fn wait_for_interrupt() {
+    asm::wfi();
+}
+
+/// Idle forever, waking up only to service interrupts
+pub fn wait_forever() -> ! {
+    loop {
+        asm::wfi();
+    }
+}
+
+/// Stop the core for good. Interrupts are masked so nothing runs on it anymore
+pub fn halt() -> ! {
+    exception::irq::mask();
+    loop {
+        asm::wfe();
+    }
+}
@@ -0,0 +1,301 @@
+use super::{Driver, MMIOWrapper};
+use crate::{
+    exception::{self, ExceptionKind, ExceptionResult, VectorGroup},
+    sync::IRQSafeLock,
+};
+use aarch64_cpu::asm::barrier;
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields, register_structs,
+    registers::{ReadOnly, ReadWrite, WriteOnly},
+};
+
+pub type IRQNumber = usize;
+
+/// The GIC-400 in the BCM2711 implements 256 interrupt lines
+pub const MAX_IRQ_NUMBER: IRQNumber = 255;
+const NUM_IRQS: usize = MAX_IRQ_NUMBER + 1;
+
+/// First shared peripheral interrupt. Everything below is banked per core
+const SPI_START: IRQNumber = 32;
+
+/// Software generated interrupts 0-15 are used for inter-processor interrupts
+pub const NUM_SGIS: IRQNumber = 16;
+
+/// Interrupt ID returned by GICC_IAR when there is no pending interrupt
+const SPURIOUS_IRQ: IRQNumber = 1023;
+
+register_bitfields! {u32,
+    /// Distributor control register
+    GICD_CTLR [
+        /// Enables forwarding of pending interrupts from the distributor to the CPU interfaces
+        EnableGrp1 OFFSET(1) NUMBITS(1),
+        EnableGrp0 OFFSET(0) NUMBITS(1),
+    ],
+
+    /// Interrupt controller type register
+    GICD_TYPER [
+        /// The maximum number of interrupts supported is 32 * (ITLinesNumber + 1)
+        ITLinesNumber OFFSET(0) NUMBITS(5),
+    ],
+
+    /// Software generated interrupt register
+    GICD_SGIR [
+        /// Send to the cores in CPUTargetList, or to every core except the one writing the
+        /// register
+        TargetListFilter OFFSET(24) NUMBITS(2) [
+            TargetList = 0b00,
+            AllOthers = 0b01,
+        ],
+        /// One bit per core
+        CPUTargetList OFFSET(16) NUMBITS(8) [],
+        SGIINTID OFFSET(0) NUMBITS(4) []
+    ],
+
+    /// CPU interface control register
+    GICC_CTLR [
+        EnableGrp1 OFFSET(1) NUMBITS(1),
+        EnableGrp0 OFFSET(0) NUMBITS(1),
+    ],
+
+    /// Interrupt priority mask register. Only interrupts with a higher priority (lower value)
+    /// than this are signaled to the core
+    GICC_PMR [
+        Priority OFFSET(0) NUMBITS(8),
+    ],
+}
+
+register_structs! {
+    pub DistributorRegisters {
+        (0x000 => ctlr: ReadWrite<u32, GICD_CTLR::Register>),
+        (0x004 => typer: ReadOnly<u32, GICD_TYPER::Register>),
+        (0x008 => _res1),
+        (0x100 => isenabler: [ReadWrite<u32>; 32]),
+        (0x180 => icenabler: [ReadWrite<u32>; 32]),
+        (0x200 => _res2),
+        (0x400 => ipriorityr: [ReadWrite<u32>; 255]),
+        (0x7FC => _res3),
+        (0x800 => itargetsr: [ReadWrite<u32>; 255]),
+        (0xBFC => _res4),
+        (0xF00 => sgir: WriteOnly<u32, GICD_SGIR::Register>),
+        (0xF04 => @END),
+    }
+}
+
+register_structs! {
+    pub CPUInterfaceRegisters {
+        (0x000 => ctlr: ReadWrite<u32, GICC_CTLR::Register>),
+        (0x004 => pmr: ReadWrite<u32, GICC_PMR::Register>),
+        (0x008 => _res1),
+        (0x00C => iar: ReadOnly<u32>),
+        (0x010 => eoir: WriteOnly<u32>),
+        (0x014 => @END),
+    }
+}
+
+pub trait IRQHandler {
+    fn handle(&self) -> Result<(), &'static str>;
+}
+
+#[derive(Copy, Clone)]
+pub struct IRQHandlerDescriptor {
+    pub number: IRQNumber,
+    pub name: &'static str,
+    pub handler: &'static (dyn IRQHandler + Sync),
+}
+
+struct GICDriverInner {
+    gicd: MMIOWrapper<DistributorRegisters>,
+    gicc: MMIOWrapper<CPUInterfaceRegisters>,
+
+    handlers: [Option<IRQHandlerDescriptor>; NUM_IRQS],
+}
+
+impl GICDriverInner {
+    fn init(&self) {
+        self.init_distributor();
+        self.init_cpu_interface();
+    }
+
+    fn init_distributor(&self) {
+        let gicd = &self.gicd;
+
+        gicd.ctlr.set(0);
+
+        // Route all shared interrupts to core 0. The first 8 target registers are banked per core
+        // and read only
+        let lines = self.num_irqs().min(NUM_IRQS);
+        for reg in &gicd.itargetsr[(SPI_START / 4)..(lines / 4)] {
+            reg.set(0x01010101);
+        }
+
+        // Start with every interrupt disabled
+        for reg in &gicd.icenabler[0..(lines / 32)] {
+            reg.set(u32::MAX);
+        }
+
+        gicd.ctlr
+            .write(GICD_CTLR::EnableGrp0::SET + GICD_CTLR::EnableGrp1::SET);
+    }
+
+    /// The CPU interface is banked, so this has to run on every core that wants interrupts
+    fn init_cpu_interface(&self) {
+        let gicc = &self.gicc;
+
+        // Let everything through
+        gicc.pmr.write(GICC_PMR::Priority.val(0xFF));
+        gicc.ctlr
+            .write(GICC_CTLR::EnableGrp0::SET + GICC_CTLR::EnableGrp1::SET);
+    }
+
+    fn num_irqs(&self) -> usize {
+        ((self.gicd.typer.read(GICD_TYPER::ITLinesNumber) + 1) * 32) as usize
+    }
+
+    fn register_handler(&mut self, descriptor: IRQHandlerDescriptor) -> Result<(), &'static str> {
+        let slot = self
+            .handlers
+            .get_mut(descriptor.number)
+            .ok_or("IRQ number out of range")?;
+        if slot.is_some() {
+            return Err("IRQ handler already registered");
+        }
+
+        *slot = Some(descriptor);
+        Ok(())
+    }
+
+    fn enable(&self, irq: IRQNumber) {
+        let (reg, bit) = (irq / 32, irq % 32);
+        self.gicd.isenabler[reg].set(1 << bit);
+    }
+
+    fn disable(&self, irq: IRQNumber) {
+        let (reg, bit) = (irq / 32, irq % 32);
+        self.gicd.icenabler[reg].set(1 << bit);
+    }
+
+    fn set_priority(&self, irq: IRQNumber, priority: u8) {
+        let (reg, shift) = (irq / 4, (irq % 4) * 8);
+
+        let reg = &self.gicd.ipriorityr[reg];
+        let mut val = reg.get();
+        val &= !(0xFF << shift);
+        val |= (priority as u32) << shift;
+        reg.set(val);
+    }
+
+    /// Returns the interrupt id and the raw acknowledge value, which has to be passed back to
+    /// `end_irq`
+    fn acknowledge_irq(&self) -> Option<(IRQNumber, u32)> {
+        let iar = self.gicc.iar.get();
+        let irq = (iar & 0x3FF) as IRQNumber;
+        if irq == SPURIOUS_IRQ {
+            return None;
+        }
+        Some((irq, iar))
+    }
+
+    fn end_irq(&self, iar: u32) {
+        self.gicc.eoir.set(iar);
+    }
+}
+
+/// Cores a software generated interrupt is sent to
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug)]
+pub enum SGITarget {
+    Core(usize),
+    AllOthers,
+}
+
+pub struct GICDriver {
+    inner: IRQSafeLock<GICDriverInner>,
+    /// Used without the lock for sending SGIs, which is a single register write. This way a
+    /// panicking core can still stop the others
+    sgi_gicd: MMIOWrapper<DistributorRegisters>,
+}
+
+#[allow(dead_code)]
+impl GICDriver {
+    pub const fn new(gicd_base: usize, gicc_base: usize) -> Self {
+        Self {
+            inner: IRQSafeLock::new(GICDriverInner {
+                gicd: MMIOWrapper::new(gicd_base),
+                gicc: MMIOWrapper::new(gicc_base),
+                handlers: [None; NUM_IRQS],
+            }),
+            sgi_gicd: MMIOWrapper::new(gicd_base),
+        }
+    }
+
+    /// Set up the banked CPU interface of a secondary core
+    pub fn init_current_core(&self) {
+        self.inner.lock(|i| i.init_cpu_interface())
+    }
+
+    pub fn send_sgi(&self, sgi: IRQNumber, target: SGITarget) {
+        assert!(sgi < NUM_SGIS, "Invalid SGI number {}", sgi);
+
+        let filter = match target {
+            SGITarget::Core(core) => {
+                GICD_SGIR::TargetListFilter::TargetList + GICD_SGIR::CPUTargetList.val(1 << core)
+            }
+            SGITarget::AllOthers => GICD_SGIR::TargetListFilter::AllOthers,
+        };
+
+        // Whatever the receiver is supposed to look at has to be visible before the interrupt
+        barrier::dsb(barrier::ISHST);
+        self.sgi_gicd
+            .sgir
+            .write(filter + GICD_SGIR::SGIINTID.val(sgi as u32));
+    }
+
+    /// Register a handler for an interrupt. The line stays disabled until `enable` is called
+    pub fn register_handler(&self, descriptor: IRQHandlerDescriptor) -> Result<(), &'static str> {
+        self.inner.lock(|i| i.register_handler(descriptor))
+    }
+
+    pub fn enable(&self, irq: IRQNumber) {
+        self.inner.lock(|i| i.enable(irq))
+    }
+    pub fn disable(&self, irq: IRQNumber) {
+        self.inner.lock(|i| i.disable(irq))
+    }
+
+    /// Lower values mean higher priority
+    pub fn set_priority(&self, irq: IRQNumber, priority: u8) {
+        self.inner.lock(|i| i.set_priority(irq, priority))
+    }
+
+    /// Acknowledge, dispatch and end every pending interrupt.
+    /// Handlers run without the driver lock held, so they are free to call back into the GIC
+    pub fn handle_pending_irqs(&self) {
+        while let Some((irq, iar)) = self.inner.lock(|i| i.acknowledge_irq()) {
+            match self.inner.lock(|i| i.handlers.get(irq).copied().flatten()) {
+                None => panic!("No handler registered for IRQ {}", irq),
+                Some(d) => {
+                    if let Err(s) = d.handler.handle() {
+                        panic!("IRQ handler {} failed:\n{}", d.name, s)
+                    }
+                }
+            }
+
+            self.inner.lock(|i| i.end_irq(iar));
+        }
+    }
+}
+impl Driver for GICDriver {
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        self.inner.lock(|i| i.init());
+
+        for group in [VectorGroup::CurrentSPx, VectorGroup::LowerAArch64] {
+            exception::register_handler(group, ExceptionKind::Irq, |_| {
+                super::gic().handle_pending_irqs();
+                ExceptionResult::Handled
+            })?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use super::Driver;
-use crate::sync::NullLock;
+use crate::sync::IRQSafeLock;
 
 #[allow(dead_code)]
 pub enum Function {
@@ -57,14 +57,14 @@ impl GPIODriverInner {
 }
 
 pub struct GPIODriver {
-    inner: NullLock<GPIODriverInner>,
+    inner: IRQSafeLock<GPIODriverInner>,
 }
 
 #[allow(dead_code)]
 impl GPIODriver {
     pub const fn new(base: usize) -> Self {
         Self {
-            inner: NullLock::new(GPIODriverInner {
+            inner: IRQSafeLock::new(GPIODriverInner {
                 gpfsel0: base + 0,
                 gpset0: base + 0x1C,
                 gpclr0: base + 0x28,
@@ -1,50 +1,56 @@
 use super::DriverDescriptor;
-use crate::{info, sync::NullLock};
+use crate::{info, sync::IRQSafeLock};
+use alloc::vec::Vec;
 
-struct DriverManagerInner<const N: usize> {
-    drivers: [Option<DriverDescriptor>; N],
-    idx: usize,
+struct DriverManagerInner {
+    drivers: Vec<DriverDescriptor>,
 }
 
-impl<const N: usize> DriverManagerInner<N> {
+impl DriverManagerInner {
     const fn new() -> Self {
         Self {
-            idx: 0,
-            drivers: [const { None }; N],
+            drivers: Vec::new(),
         }
     }
 
     fn register_driver(&mut self, driver_descriptor: DriverDescriptor) {
-        self.drivers[self.idx] = Some(driver_descriptor);
-        self.idx += 1;
+        self.drivers.push(driver_descriptor);
     }
 
     unsafe fn init(&mut self) {
-        self.drivers
-            .iter()
-            .filter_map(|d| d.as_ref())
-            .for_each(|d| {
-                if let Err(s) = d.driver.init() {
-                    panic!("Driver {} failed to initialize:\n{}", d.name, s)
+        self.drivers.iter().for_each(|d| {
+            if let Err(s) = d.driver.init() {
+                panic!("Driver {} failed to initialize:\n{}", d.name, s)
+            }
+
+            if let Some(f) = d.post_init {
+                if let Err(s) = f() {
+                    panic!("Driver {} failed to run post_init callback:\n{}", d.name, s)
                 }
+            }
+            info!("Initialized {} driver", d.name);
+        });
 
-                if let Some(f) = d.post_init {
-                    if let Err(s) = f() {
-                        panic!("Driver {} failed to run post_init callback:\n{}", d.name, s)
-                    }
+        // Only register handlers once every driver is up, the GIC has to be initialized before
+        // any interrupt line can be enabled
+        self.drivers.iter().for_each(|d| {
+            if let Some(irq) = d.irq_number {
+                if let Err(s) = d.driver.register_irq_handler(irq) {
+                    panic!("Driver {} failed to register IRQ handler:\n{}", d.name, s)
                 }
-                info!("Initialized {} driver", d.name);
-            });
+                info!("Registered {} IRQ handler on line {}", d.name, irq);
+            }
+        });
     }
 }
 
-pub struct DriverManager<const N: usize> {
-    inner: NullLock<DriverManagerInner<N>>,
+pub struct DriverManager {
+    inner: IRQSafeLock<DriverManagerInner>,
 }
-impl<const N: usize> DriverManager<N> {
+impl DriverManager {
     pub const fn new() -> Self {
         Self {
-            inner: NullLock::new(DriverManagerInner::new()),
+            inner: IRQSafeLock::new(DriverManagerInner::new()),
         }
     }
 
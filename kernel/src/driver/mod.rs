@@ -1,31 +1,60 @@
 use core::ops::Deref;
 
-use crate::{log, memory};
+use crate::{
+    log,
+    memory::map::{mmio, phys_to_virt},
+    time,
+};
+use gic::{GICDriver, IRQNumber};
 use gpio::GPIODriver;
 use manager::DriverManager;
 use uart::UARTDriver;
 
+pub mod gic;
 pub mod gpio;
 pub mod manager;
 pub mod uart;
 
 pub trait Driver {
     unsafe fn init(&self) -> Result<(), &'static str>;
+
+    /// Called by the driver manager after every driver has been initialized, for drivers that
+    /// have an IRQ number in their descriptor
+    fn register_irq_handler(&'static self, _irq: IRQNumber) -> Result<(), &'static str> {
+        Err("Driver does not handle interrupts")
+    }
 }
 
 pub struct DriverDescriptor {
     pub name: &'static str,
     pub driver: &'static (dyn Driver + Sync),
     pub post_init: Option<unsafe fn() -> Result<(), &'static str>>,
+    pub irq_number: Option<IRQNumber>,
 }
 
-pub const DRIVER_COUNT: usize = 2;
-static DRIVER_MANAGER: DriverManager<DRIVER_COUNT> = DriverManager::new();
+static DRIVER_MANAGER: DriverManager = DriverManager::new();
+
+static GIC_DRIVER: GICDriver = GICDriver::new(
+    phys_to_virt(mmio::GICD_START),
+    phys_to_virt(mmio::GICC_START),
+);
+static GPIO_DRIVER: GPIODriver = GPIODriver::new(phys_to_virt(mmio::GPIO_START));
+pub static UART_DRIVER: UARTDriver = UARTDriver::new(phys_to_virt(mmio::UART0_START));
 
-static GPIO_DRIVER: GPIODriver = GPIODriver::new(memory::map::mmio::GPIO_START);
-pub static UART_DRIVER: UARTDriver = UARTDriver::new(memory::map::mmio::UART0_START);
+/// VideoCore interrupt 57 (PL011 UARTs) is SPI 121 on the GIC
+const UART0_IRQ: IRQNumber = 153;
+/// Non-secure EL1 physical timer, PPI 14
+const PHYS_TIMER_IRQ: IRQNumber = 30;
 
 pub unsafe fn setup_drivers() {
+    // Registered first so the distributor is ready before anyone enables an interrupt line
+    let gic_descriptor = DriverDescriptor {
+        name: "GIC",
+        driver: &GIC_DRIVER,
+        post_init: None,
+        irq_number: None,
+    };
+
     let gpio_descriptor = DriverDescriptor {
         name: "GPIO",
         driver: &GPIO_DRIVER,
@@ -33,6 +62,7 @@ pub unsafe fn setup_drivers() {
             GPIO_DRIVER.map_uart();
             Ok(())
         }),
+        irq_number: None,
     };
 
     let uart_descriptor = DriverDescriptor {
@@ -42,16 +72,30 @@ pub unsafe fn setup_drivers() {
             log::logger().set_writer(&UART_DRIVER);
             Ok(())
         }),
+        irq_number: Some(UART0_IRQ),
     };
 
+    let timer_descriptor = DriverDescriptor {
+        name: "Timer",
+        driver: time::timer_queue(),
+        post_init: None,
+        irq_number: Some(PHYS_TIMER_IRQ),
+    };
+
+    DRIVER_MANAGER.register_driver(gic_descriptor);
     DRIVER_MANAGER.register_driver(gpio_descriptor);
     DRIVER_MANAGER.register_driver(uart_descriptor);
+    DRIVER_MANAGER.register_driver(timer_descriptor);
 }
 
-pub fn manager() -> &'static DriverManager<DRIVER_COUNT> {
+pub fn manager() -> &'static DriverManager {
     &DRIVER_MANAGER
 }
 
+pub fn gic() -> &'static GICDriver {
+    &GIC_DRIVER
+}
+
 struct MMIOWrapper<T> {
     addr: usize,
     _t: core::marker::PhantomData<fn() -> T>,
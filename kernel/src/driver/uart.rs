@@ -1,13 +1,35 @@
-use super::{Driver, MMIOWrapper};
-use crate::{log::LogWrite, sync::NullLock};
+use super::{
+    gic::{IRQHandler, IRQHandlerDescriptor, IRQNumber},
+    Driver, MMIOWrapper,
+};
+use crate::{log::LogWrite, sync::IRQSafeLock};
 use core::{arch::asm, fmt::Write};
 use tock_registers::{
-    interfaces::{Readable, Writeable},
+    interfaces::{ReadWriteable, Readable, Writeable},
     register_bitfields, register_structs,
     registers::{ReadOnly, ReadWrite, WriteOnly},
 };
 
 register_bitfields! {u32,
+    /// Data register
+    DR [
+        /// Overrun error. Set if data is received and the receive FIFO is already full
+        OE OFFSET(11) NUMBITS(1),
+
+        /// Break error. Set if the received data input was held LOW for longer than a
+        /// full-word transmission time
+        BE OFFSET(10) NUMBITS(1),
+
+        /// Parity error. Set if the parity of the received data character does not match the
+        /// parity selected in UART_LCRH
+        PE OFFSET(9) NUMBITS(1),
+
+        /// Framing error. Set if the received character did not have a valid stop bit
+        FE OFFSET(8) NUMBITS(1),
+
+        DATA OFFSET(0) NUMBITS(8),
+    ],
+
     /// Flag register
     FR [
         /// Transmit FIFO empty. The meaning of this bit depends on the state of the FEN bit in
@@ -92,15 +114,81 @@ register_bitfields! {u32,
         EN OFFSET(0) NUMBITS(1)
     ],
 
+    // Interrupt FIFO level select register
+    IFLS [
+        /// Receive interrupt FIFO level select. The receive interrupt is asserted once the FIFO
+        /// becomes at least this full
+        RXIFLSEL OFFSET(3) NUMBITS(3) [
+            OneEighth = 0b000,
+            OneQuarter = 0b001,
+            OneHalf = 0b010,
+            ThreeQuarters = 0b011,
+            SevenEighths = 0b100,
+        ],
+
+        /// Transmit interrupt FIFO level select. The transmit interrupt is asserted once the FIFO
+        /// drains to this level
+        TXIFLSEL OFFSET(0) NUMBITS(3) [
+            OneEighth = 0b000,
+            OneQuarter = 0b001,
+            OneHalf = 0b010,
+            ThreeQuarters = 0b011,
+            SevenEighths = 0b100,
+        ],
+    ],
+
+    // Interrupt mask set/clear register. Setting a bit enables the interrupt
+    IMSC [
+        /// Overrun error interrupt mask
+        OEIM OFFSET(10) NUMBITS(1),
+
+        /// Break error interrupt mask
+        BEIM OFFSET(9) NUMBITS(1),
+
+        /// Parity error interrupt mask
+        PEIM OFFSET(8) NUMBITS(1),
+
+        /// Framing error interrupt mask
+        FEIM OFFSET(7) NUMBITS(1),
+
+        /// Receive timeout interrupt mask
+        RTIM OFFSET(6) NUMBITS(1),
+
+        /// Transmit interrupt mask
+        TXIM OFFSET(5) NUMBITS(1),
+
+        /// Receive interrupt mask
+        RXIM OFFSET(4) NUMBITS(1),
+    ],
+
+    // Masked interrupt status register
+    MIS [
+        OEMIS OFFSET(10) NUMBITS(1),
+        BEMIS OFFSET(9) NUMBITS(1),
+        PEMIS OFFSET(8) NUMBITS(1),
+        FEMIS OFFSET(7) NUMBITS(1),
+        RTMIS OFFSET(6) NUMBITS(1),
+        TXMIS OFFSET(5) NUMBITS(1),
+        RXMIS OFFSET(4) NUMBITS(1),
+    ],
+
     // Interrupt clear register
     ICR [
+        OEIC OFFSET(10) NUMBITS(1),
+        BEIC OFFSET(9) NUMBITS(1),
+        PEIC OFFSET(8) NUMBITS(1),
+        FEIC OFFSET(7) NUMBITS(1),
+        RTIC OFFSET(6) NUMBITS(1),
+        TXIC OFFSET(5) NUMBITS(1),
+        RXIC OFFSET(4) NUMBITS(1),
+
         ALL OFFSET(0) NUMBITS(11),
     ]
 }
 
 register_structs! {
     pub UartRegisters {
-        (0x00 => dr: ReadWrite<u32>),
+        (0x00 => dr: ReadWrite<u32, DR::Register>),
         (0x04 => _res1),
         (0x18 => fr: ReadOnly<u32, FR::Register>),
         (0x1c => _res2),
@@ -108,20 +196,88 @@ register_structs! {
         (0x28 => fbrd: WriteOnly<u32, FBRD::Register>),
         (0x2c => lcrh: WriteOnly<u32, LCRH::Register>),
         (0x30 => cr: WriteOnly<u32, CR::Register>),
-        (0x34 => _res3),
+        (0x34 => ifls: ReadWrite<u32, IFLS::Register>),
+        (0x38 => imsc: ReadWrite<u32, IMSC::Register>),
+        (0x3c => _res3),
+        (0x40 => mis: ReadOnly<u32, MIS::Register>),
         (0x44 => icr: WriteOnly<u32, ICR::Register>),
         (0x48 => @END),
     }
 }
 
+const RX_BUF_SIZE: usize = 1024;
+const TX_BUF_SIZE: usize = 4096;
+
+/// Fixed size FIFO of bytes. Pushing into a full buffer fails instead of overwriting
+struct RingBuffer<const N: usize> {
+    buf: [u8; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, b: u8) -> Result<(), u8> {
+        if self.len == N {
+            return Err(b);
+        }
+
+        self.buf[(self.head + self.len) % N] = b;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let b = self.buf[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(b)
+    }
+}
+
+/// Receive errors seen since boot
+#[derive(Clone, Copy, Debug)]
+pub struct UARTErrorStats {
+    pub overrun: u64,
+    pub framing: u64,
+    pub parity: u64,
+    pub breaks: u64,
+    /// Bytes dropped because the receive buffer was full
+    pub dropped: u64,
+}
+
 struct UARTDriverInner {
     regs: MMIOWrapper<UartRegisters>,
+
+    rx_buf: RingBuffer<RX_BUF_SIZE>,
+    err_stats: UARTErrorStats,
+
+    tx_buf: RingBuffer<TX_BUF_SIZE>,
+    /// Writes go through `tx_buf` and are drained by the TX interrupt. Cleared until the
+    /// interrupt is registered and again on panic, in which case every write blocks
+    tx_irq: bool,
 }
 impl UARTDriverInner {
     fn init(&self) {
         let regs = &self.regs;
 
         regs.cr.write(CR::EN::CLEAR);
+        regs.imsc.set(0);
         regs.icr.write(ICR::ALL::CLEAR);
 
         // Set baudrate to 921600
@@ -129,19 +285,27 @@ impl UARTDriverInner {
         regs.fbrd.write(FBRD::FRACT_BAUDDIV.val(16));
 
         regs.lcrh.write(LCRH::FEN::Enable + LCRH::WLEN::Bits8);
+        regs.ifls
+            .write(IFLS::RXIFLSEL::OneHalf + IFLS::TXIFLSEL::OneEighth);
 
         regs.cr.write(CR::EN::SET + CR::TXE::SET + CR::RXE::SET);
     }
 
-    fn flush(&self) {
-        while self.regs.fr.matches_all(FR::TXFF::SET) {
+    /// Drain the transmit buffer without relying on interrupts and wait until the last stop bit
+    /// has left the shift register
+    fn flush(&mut self) {
+        while let Some(c) = self.tx_buf.pop() {
+            self.write_sync(c);
+        }
+
+        while self.regs.fr.matches_all(FR::BUSY::SET) {
             unsafe {
                 asm!("nop");
             }
         }
     }
 
-    fn write(&mut self, c: u8) {
+    fn write_sync(&self, c: u8) {
         while self.regs.fr.matches_all(FR::TXFF::SET) {
             unsafe {
                 asm!("nop");
@@ -151,21 +315,113 @@ impl UARTDriverInner {
         self.regs.dr.set(c as u32);
     }
 
-    fn read_blocking(&self) -> u8 {
-        while self.regs.fr.matches_all(FR::RXFE::SET) {
-            unsafe {
-                asm!("nop");
+    fn write(&mut self, c: u8) {
+        if !self.tx_irq {
+            self.write_sync(c);
+            return;
+        }
+
+        // Buffer is full, make room by pushing the oldest byte out ourselves
+        if let Err(c) = self.tx_buf.push(c) {
+            let oldest = self.tx_buf.pop().unwrap();
+            self.write_sync(oldest);
+            self.tx_buf.push(c).unwrap();
+        }
+
+        self.fill_tx_fifo();
+    }
+
+    /// Move as much of the transmit buffer into the FIFO as fits. The TX interrupt is only left
+    /// enabled while there is something buffered
+    fn fill_tx_fifo(&mut self) {
+        while !self.regs.fr.matches_all(FR::TXFF::SET) {
+            match self.tx_buf.pop() {
+                Some(c) => self.regs.dr.set(c as u32),
+                None => break,
             }
         }
 
-        self.regs.dr.get() as u8
+        if self.tx_buf.is_empty() {
+            self.regs.imsc.modify(IMSC::TXIM::CLEAR);
+        } else {
+            self.regs.imsc.modify(IMSC::TXIM::SET);
+        }
     }
 
-    fn read(&self) -> Option<u8> {
-        if self.regs.fr.matches_all(FR::RXFE::SET) {
-            return None;
+    fn enable_irqs(&mut self) {
+        self.regs
+            .icr
+            .write(ICR::RXIC::SET + ICR::RTIC::SET + ICR::TXIC::SET);
+        self.regs.imsc.write(IMSC::RXIM::SET + IMSC::RTIM::SET);
+        self.tx_irq = true;
+    }
+
+    fn set_sync(&mut self) {
+        self.tx_irq = false;
+        self.regs.imsc.modify(IMSC::TXIM::CLEAR);
+        self.flush();
+    }
+
+    /// Move everything sitting in the receive FIFO into the ring buffer
+    fn drain_rx_fifo(&mut self) {
+        while !self.regs.fr.matches_all(FR::RXFE::SET) {
+            let dr = self.regs.dr.extract();
+
+            if dr.is_set(DR::OE) {
+                self.err_stats.overrun += 1;
+            }
+            if dr.is_set(DR::BE) {
+                self.err_stats.breaks += 1;
+            }
+            if dr.is_set(DR::PE) {
+                self.err_stats.parity += 1;
+            }
+            if dr.is_set(DR::FE) {
+                self.err_stats.framing += 1;
+            }
+
+            // An overrun only means a later character was lost, this one is still valid
+            if dr.matches_any(DR::BE::SET + DR::PE::SET + DR::FE::SET) {
+                continue;
+            }
+
+            if self.rx_buf.push(dr.read(DR::DATA) as u8).is_err() {
+                self.err_stats.dropped += 1;
+            }
+        }
+    }
+
+    fn handle_irq(&mut self) {
+        let mis = self.regs.mis.extract();
+
+        if mis.matches_any(MIS::RXMIS::SET + MIS::RTMIS::SET) {
+            self.drain_rx_fifo();
+        }
+        if mis.is_set(MIS::TXMIS) {
+            self.fill_tx_fifo();
+        }
+
+        self.regs
+            .icr
+            .write(ICR::RXIC::SET + ICR::RTIC::SET + ICR::TXIC::SET);
+    }
+
+    fn read_blocking(&mut self) -> u8 {
+        loop {
+            if let Some(b) = self.read() {
+                return b;
+            }
+
+            unsafe {
+                asm!("nop");
+            }
         }
-        Some(self.read_blocking())
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        // Also covers the time before the RX interrupt is registered
+        self.drain_rx_fifo();
+        self.rx_buf.pop()
     }
 }
 
@@ -179,15 +435,27 @@ impl Write for UARTDriverInner {
 }
 
 pub struct UARTDriver {
-    inner: NullLock<UARTDriverInner>,
+    inner: IRQSafeLock<UARTDriverInner>,
 }
 
 #[allow(dead_code)]
 impl UARTDriver {
     pub const fn new(base: usize) -> Self {
         Self {
-            inner: NullLock::new(UARTDriverInner {
+            inner: IRQSafeLock::new(UARTDriverInner {
                 regs: MMIOWrapper::new(base),
+
+                rx_buf: RingBuffer::new(),
+                err_stats: UARTErrorStats {
+                    overrun: 0,
+                    framing: 0,
+                    parity: 0,
+                    breaks: 0,
+                    dropped: 0,
+                },
+
+                tx_buf: RingBuffer::new(),
+                tx_irq: false,
             }),
         }
     }
@@ -209,11 +477,17 @@ impl UARTDriver {
         self.inner.lock(|i| i.write_str(s)).unwrap();
     }
 
-    /// Block until the transmit FIFO is empty
+    /// Block until everything written so far has been sent
     pub fn flush(&self) {
         self.inner.lock(|i| i.flush());
     }
 
+    /// Stop using the TX interrupt and make every following write block until it reaches the
+    /// FIFO
+    pub fn set_sync(&self) {
+        self.inner.lock(|i| i.set_sync());
+    }
+
     /// Block until a u8 is sent
     pub fn read_blocking(&self) -> u8 {
         self.inner.lock(|i| i.read_blocking())
@@ -265,6 +539,11 @@ impl UARTDriver {
         })
     }
 
+    /// Receive errors counted since boot
+    pub fn error_stats(&self) -> UARTErrorStats {
+        self.inner.lock(|i| i.err_stats)
+    }
+
     pub fn write_fmt(&self, args: core::fmt::Arguments) -> core::fmt::Result {
         self.inner.lock(|i| i.write_fmt(args))
     }
@@ -281,10 +560,42 @@ impl Driver for UARTDriver {
         self.inner.lock(|i| i.init());
         Ok(())
     }
+
+    fn register_irq_handler(&'static self, irq: IRQNumber) -> Result<(), &'static str> {
+        let gic = super::gic();
+        gic.register_handler(IRQHandlerDescriptor {
+            number: irq,
+            name: "UART",
+            handler: self,
+        })?;
+
+        self.inner.lock(|i| i.enable_irqs());
+        gic.enable(irq);
+        Ok(())
+    }
+}
+
+impl IRQHandler for UARTDriver {
+    fn handle(&self) -> Result<(), &'static str> {
+        self.inner.lock(|i| i.handle_irq());
+        Ok(())
+    }
 }
 
 impl LogWrite for UARTDriver {
     fn write_str(&self, s: &str) {
         self.inner.lock(|i| i.write_str(s).unwrap())
     }
+
+    fn flush(&self) {
+        self.inner.lock(|i| i.flush())
+    }
+
+    fn set_sync(&self) {
+        self.inner.lock(|i| i.set_sync())
+    }
+
+    unsafe fn force_unlock(&self) {
+        self.inner.force_unlock();
+    }
 }
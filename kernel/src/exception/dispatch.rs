@@ -0,0 +1,119 @@
+use super::{default_exception_handler, ExceptionContext};
+use crate::sync::IRQSafeLock;
+use aarch64_cpu::registers::ESR_EL1;
+
+/// Which quarter of the vector table an exception came through
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VectorGroup {
+    /// Current EL while using SP_EL0
+    CurrentSP0,
+    /// Current EL while using SP_ELx, x != 0
+    CurrentSPx,
+    LowerAArch64,
+    LowerAArch32,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ExceptionKind {
+    /// Synchronous exception with the given exception class
+    Sync(ESR_EL1::EC::Value),
+    Irq,
+    Fiq,
+    SError,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExceptionResult {
+    /// Return to `elr_el1` with the (possibly modified) context
+    Handled,
+    /// Fall through to the default handler, which reports the exception and panics
+    Unhandled,
+}
+
+pub type ExceptionHandler = fn(&mut ExceptionContext) -> ExceptionResult;
+
+const NUM_GROUPS: usize = 4;
+const NUM_CLASSES: usize = 64;
+/// One slot per exception class, then IRQ, FIQ and SError
+const NUM_KINDS: usize = NUM_CLASSES + 3;
+
+struct HandlerTable {
+    handlers: [[Option<ExceptionHandler>; NUM_KINDS]; NUM_GROUPS],
+}
+
+impl HandlerTable {
+    const fn new() -> Self {
+        Self {
+            handlers: [[None; NUM_KINDS]; NUM_GROUPS],
+        }
+    }
+
+    fn slot(&mut self, group: VectorGroup, kind: ExceptionKind) -> &mut Option<ExceptionHandler> {
+        let idx = match kind {
+            ExceptionKind::Sync(ec) => ec as usize,
+            ExceptionKind::Irq => NUM_CLASSES,
+            ExceptionKind::Fiq => NUM_CLASSES + 1,
+            ExceptionKind::SError => NUM_CLASSES + 2,
+        };
+
+        &mut self.handlers[group as usize][idx]
+    }
+}
+
+static HANDLERS: IRQSafeLock<HandlerTable> = IRQSafeLock::new(HandlerTable::new());
+
+/// Install `handler` for exceptions of `kind` taken through `group`.
+/// Only one handler can be registered per slot
+pub fn register_handler(
+    group: VectorGroup,
+    kind: ExceptionKind,
+    handler: ExceptionHandler,
+) -> Result<(), &'static str> {
+    HANDLERS.lock(|h| {
+        let slot = h.slot(group, kind);
+        if slot.is_some() {
+            return Err("Exception handler already registered");
+        }
+
+        *slot = Some(handler);
+        Ok(())
+    })
+}
+
+/// Remove the handler for `kind` taken through `group`, if there is one
+#[allow(dead_code)]
+pub fn unregister_handler(group: VectorGroup, kind: ExceptionKind) {
+    HANDLERS.lock(|h| *h.slot(group, kind) = None)
+}
+
+/// Called for every exception from the vector table
+pub(super) fn dispatch(group: VectorGroup, kind: ExceptionKind, ctx: &mut ExceptionContext) {
+    // Handler runs without the lock held so it can register handlers itself
+    let handler = HANDLERS.lock(|h| *h.slot(group, kind));
+
+    if kind == ExceptionKind::Irq {
+        super::irq::enter();
+    }
+    let res = match handler {
+        Some(f) => f(ctx),
+        None => ExceptionResult::Unhandled,
+    };
+    if kind == ExceptionKind::Irq {
+        super::irq::exit();
+    }
+
+    if res == ExceptionResult::Unhandled {
+        default_exception_handler(ctx);
+    }
+}
+
+/// Like `dispatch`, with the kind taken from the exception class in ESR_EL1
+pub(super) fn dispatch_sync(group: VectorGroup, ctx: &mut ExceptionContext) {
+    match ctx.exception_class() {
+        Some(ec) => dispatch(group, ExceptionKind::Sync(ec), ctx),
+        None => default_exception_handler(ctx),
+    }
+}
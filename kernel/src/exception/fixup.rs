@@ -0,0 +1,58 @@
+use super::{ExceptionContext, ExceptionKind, ExceptionResult, VectorGroup};
+use aarch64_cpu::registers::{Readable, ESR_EL1};
+use core::cell::UnsafeCell;
+
+/// Register that receives ESR_EL1 when an instruction in the fixup table faults. It is never
+/// zero for an abort, so code can zero it beforehand and check it afterwards
+const FIXUP_ESR_REG: usize = 16;
+/// Register that receives FAR_EL1 when an instruction in the fixup table faults
+const FIXUP_FAR_REG: usize = 17;
+
+/// Entry in the `__ex_table` linker section
+#[repr(C)]
+struct FixupEntry {
+    /// Address of an instruction that is allowed to fault
+    insn: u64,
+    /// Where to resume if it does
+    fixup: u64,
+}
+
+extern "Rust" {
+    static __ex_table_start: UnsafeCell<FixupEntry>;
+    static __ex_table_end: UnsafeCell<FixupEntry>;
+}
+
+fn fixup_table() -> &'static [FixupEntry] {
+    unsafe {
+        let start = __ex_table_start.get() as *const FixupEntry;
+        let end = __ex_table_end.get() as *const FixupEntry;
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+fn search(addr: u64) -> Option<u64> {
+    fixup_table()
+        .iter()
+        .find(|e| e.insn == addr)
+        .map(|e| e.fixup)
+}
+
+fn handle_data_abort(ctx: &mut ExceptionContext) -> ExceptionResult {
+    let Some(fixup) = search(ctx.elr_el1) else {
+        return ExceptionResult::Unhandled;
+    };
+
+    ctx.regs[FIXUP_ESR_REG] = ctx.esr_el1.get();
+    ctx.regs[FIXUP_FAR_REG] = ctx.far_el1;
+    ctx.elr_el1 = fixup;
+
+    ExceptionResult::Handled
+}
+
+pub(super) fn init() -> Result<(), &'static str> {
+    super::register_handler(
+        VectorGroup::CurrentSPx,
+        ExceptionKind::Sync(ESR_EL1::EC::Value::DataAbortCurrentEL),
+        handle_data_abort,
+    )
+}
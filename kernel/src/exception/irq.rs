@@ -0,0 +1,66 @@
+use crate::per_cpu;
+use aarch64_cpu::registers::{Readable, Writeable, DAIF};
+use core::arch::asm;
+
+const DAIF_IRQ: u8 = 0b0010;
+
+#[derive(Copy, Clone, Debug)]
+pub struct IRQStats {
+    /// How many IRQ handlers are running on the core, nested ones included
+    pub depth: usize,
+    /// IRQs taken since boot
+    pub count: u64,
+}
+
+per_cpu! {
+    static IRQ_STATS: IRQStats = IRQStats { depth: 0, count: 0 };
+}
+
+/// IRQ statistics of the current core
+#[allow(dead_code)]
+pub fn stats() -> IRQStats {
+    IRQ_STATS.with(|s| *s)
+}
+
+/// Whether the current core is running an IRQ handler
+pub fn in_interrupt() -> bool {
+    IRQ_STATS.with(|s| s.depth > 0)
+}
+
+pub(super) fn enter() {
+    IRQ_STATS.with(|s| {
+        s.depth += 1;
+        s.count += 1;
+    })
+}
+
+pub(super) fn exit() {
+    IRQ_STATS.with(|s| s.depth -= 1)
+}
+
+/// Unmask IRQs on the current core
+pub fn unmask() {
+    unsafe { asm!("msr DAIFClr, {}", const DAIF_IRQ, options(nostack)) };
+}
+
+/// Mask IRQs on the current core
+pub fn mask() {
+    unsafe { asm!("msr DAIFSet, {}", const DAIF_IRQ, options(nostack)) };
+}
+
+#[allow(dead_code)]
+pub fn is_masked() -> bool {
+    DAIF.is_set(DAIF::I)
+}
+
+/// Mask IRQs on the current core and return the previous DAIF value, to be passed to `restore`
+pub fn save_and_mask() -> u64 {
+    let daif = DAIF.get();
+    mask();
+    daif
+}
+
+/// Restore the exception masks saved by `save_and_mask`
+pub fn restore(daif: u64) {
+    DAIF.set(daif);
+}
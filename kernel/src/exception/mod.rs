@@ -1,14 +1,36 @@
+use crate::{
+    backtrace::Backtrace,
+    cpu,
+    memory::map::{EMERGENCY_STACK_SIZE, KERNEL_STACK_SIZE},
+    panic,
+    symbols::Symbolized,
+};
 use aarch64_cpu::{
     asm::barrier,
-    registers::{Readable, Writeable, ESR_EL1, FAR_EL1, SPSR_EL1, VBAR_EL1},
+    registers::{Readable, Writeable, ESR_EL1, SPSR_EL1, VBAR_EL1},
 };
 use core::{
     arch::{asm, global_asm},
     fmt::Display,
 };
+use dispatch::{dispatch, dispatch_sync};
 use tock_registers::registers::InMemoryRegister;
 
-global_asm!(include_str!("exception.S"));
+mod dispatch;
+mod fixup;
+pub mod irq;
+mod syndrome;
+
+pub use dispatch::{register_handler, ExceptionKind, ExceptionResult, VectorGroup};
+pub use syndrome::Syndrome;
+
+global_asm!(
+    include_str!("exception.S"),
+    CONTEXT_SIZE = const core::mem::size_of::<ExceptionContext>(),
+    SAVE_FP = const cfg!(feature = "fp_context") as u8,
+    STACK_SHIFT = const KERNEL_STACK_SIZE.trailing_zeros(),
+    EMERGENCY_STACK_SHIFT = const EMERGENCY_STACK_SIZE.trailing_zeros(),
+);
 
 #[derive(Debug)]
 pub enum PrivilegeLevel {
@@ -33,28 +55,72 @@ pub fn current_el() -> PrivilegeLevel {
 }
 
 pub unsafe fn init_handlers() {
+    install_vectors();
+    fixup::init().unwrap();
+}
+
+/// Point the current core at the vector table. Handlers are shared, so this is all secondary
+/// cores need
+pub unsafe fn install_vectors() {
     use core::cell::UnsafeCell;
     extern "Rust" {
         static __exception_vector_start: UnsafeCell<()>;
     }
 
     VBAR_EL1.set(__exception_vector_start.get() as u64);
-    barrier::isb(barrier::SY)
+    barrier::isb(barrier::SY);
 }
 
+/// Registers saved by the vector table entry. Changes made by a handler are restored on return
 #[repr(C)]
-struct ExceptionContext {
-    regs: [u64; 30],
-    lr: u64,
+pub struct ExceptionContext {
+    pub regs: [u64; 30],
+    pub lr: u64,
 
-    elr_el1: u64,
+    pub elr_el1: u64,
 
-    spsr_el1: InMemoryRegister<u64, SPSR_EL1::Register>,
-    esr_el1: InMemoryRegister<u64, ESR_EL1::Register>,
+    pub spsr_el1: InMemoryRegister<u64, SPSR_EL1::Register>,
+    pub esr_el1: InMemoryRegister<u64, ESR_EL1::Register>,
+    /// Captured on entry, so a nested fault in the handler can't overwrite it
+    pub far_el1: u64,
+
+    pub sp_el0: u64,
+    pub tpidr_el0: u64,
+    pub tpidr_el1: u64,
+
+    #[cfg(feature = "fp_context")]
+    pub fp: FPContext,
+}
+
+/// FP/SIMD state. Rust code is free to use the vector registers, so handlers would clobber the
+/// interrupted code's values without this
+#[cfg(feature = "fp_context")]
+#[repr(C)]
+pub struct FPContext {
+    pub q: [u128; 32],
+    pub fpcr: u64,
+    pub fpsr: u64,
 }
 
 fn default_exception_handler(ctx: &ExceptionContext) {
-    panic!("CPU Exception:\n{}", ctx);
+    panic::exception_panic(format_args!(
+        "CPU Exception:\n{}{}",
+        ctx,
+        Backtrace::from_exception(ctx.elr_el1, ctx.regs[29])
+    ));
+}
+
+/// Called on the emergency stack when an exception found the kernel stack in its guard page.
+/// `tpidr_el0` holds the frame that would have been saved there
+#[no_mangle]
+extern "C" fn kernel_stack_overflow(e: &mut ExceptionContext) {
+    panic::exception_panic(format_args!(
+        "Kernel stack overflow on core {}, SP {:#X}\n{}{}",
+        cpu::id(),
+        e.tpidr_el0 as usize + core::mem::size_of::<ExceptionContext>(),
+        e,
+        Backtrace::from_exception(e.elr_el1, e.regs[29])
+    ));
 }
 
 impl Display for ExceptionContext {
@@ -67,12 +133,13 @@ impl Display for ExceptionContext {
         )?;
         writeln!(
             f,
-            "    Instr specific syndrome: {:X}",
-            self.esr_el1.read(ESR_EL1::EC)
+            "    Instr specific syndrome: {:#X}",
+            self.esr_el1.read(ESR_EL1::ISS)
         )?;
+        writeln!(f, "    Description: {}", Syndrome::new(&self.esr_el1))?;
 
         if self.has_fault_addr() {
-            writeln!(f, "FAR_EL1: {:#018X}", FAR_EL1.get())?;
+            writeln!(f, "FAR_EL1: {:#018X}", self.far_el1)?;
         }
 
         let bool_str = |x: bool, s1: &'static str, s2: &'static str| {
@@ -132,7 +199,7 @@ impl Display for ExceptionContext {
             bool_str(self.spsr_el1.is_set(SPSR_EL1::IL), "Set", "Not set")
         )?;
 
-        writeln!(f, "ELR_EL1: {:#018X}", self.elr_el1)?;
+        writeln!(f, "ELR_EL1: {}", Symbolized(self.elr_el1 as usize))?;
 
         writeln!(f, "\nGeneral purpose registers:")?;
         for i in (0..15).map(|i| i * 2) {
@@ -145,16 +212,33 @@ impl Display for ExceptionContext {
                 self.regs[i + 1]
             )?
         }
+        writeln!(f, "    lr : {}", Symbolized(self.lr as usize))?;
+
+        writeln!(f, "\nSP_EL0:    {:#018X}", self.sp_el0)?;
+        writeln!(f, "TPIDR_EL0: {:#018X}", self.tpidr_el0)?;
+        writeln!(f, "TPIDR_EL1: {:#018X}", self.tpidr_el1)?;
+
+        #[cfg(feature = "fp_context")]
+        {
+            writeln!(f, "FPCR:      {:#018X}", self.fp.fpcr)?;
+            writeln!(f, "FPSR:      {:#018X}", self.fp.fpsr)?;
+        }
 
         Ok(())
     }
 }
 
+#[allow(dead_code)]
 impl ExceptionContext {
-    fn exception_class(&self) -> Option<ESR_EL1::EC::Value> {
+    pub fn exception_class(&self) -> Option<ESR_EL1::EC::Value> {
         self.esr_el1.read_as_enum(ESR_EL1::EC)
     }
 
+    /// Resume after the instruction that caused the exception instead of retrying it
+    pub fn skip_instruction(&mut self) {
+        self.elr_el1 += 4;
+    }
+
     fn has_fault_addr(&self) -> bool {
         use ESR_EL1::EC::Value::*;
 
@@ -177,72 +261,72 @@ impl ExceptionContext {
 // Exceptions from current EL while using SP_EL0
 #[no_mangle]
 extern "C" fn current_el0_sync(e: &mut ExceptionContext) {
-    default_exception_handler(e)
+    dispatch_sync(VectorGroup::CurrentSP0, e)
 }
 #[no_mangle]
 extern "C" fn current_el0_irq(e: &mut ExceptionContext) {
-    default_exception_handler(e)
+    dispatch(VectorGroup::CurrentSP0, ExceptionKind::Irq, e)
 }
 #[no_mangle]
 extern "C" fn current_el0_fiq(e: &mut ExceptionContext) {
-    default_exception_handler(e)
+    dispatch(VectorGroup::CurrentSP0, ExceptionKind::Fiq, e)
 }
 #[no_mangle]
 extern "C" fn current_el0_serror(e: &mut ExceptionContext) {
-    default_exception_handler(e)
+    dispatch(VectorGroup::CurrentSP0, ExceptionKind::SError, e)
 }
 
 // Exceptions from cuurrent EL while using SP_ELx, x != 0
 #[no_mangle]
 extern "C" fn current_elx_sync(e: &mut ExceptionContext) {
-    default_exception_handler(e)
+    dispatch_sync(VectorGroup::CurrentSPx, e)
 }
 #[no_mangle]
 extern "C" fn current_elx_irq(e: &mut ExceptionContext) {
-    default_exception_handler(e)
+    dispatch(VectorGroup::CurrentSPx, ExceptionKind::Irq, e)
 }
 #[no_mangle]
 extern "C" fn current_elx_fiq(e: &mut ExceptionContext) {
-    default_exception_handler(e)
+    dispatch(VectorGroup::CurrentSPx, ExceptionKind::Fiq, e)
 }
 #[no_mangle]
 extern "C" fn current_elx_serror(e: &mut ExceptionContext) {
-    default_exception_handler(e)
+    dispatch(VectorGroup::CurrentSPx, ExceptionKind::SError, e)
 }
 
 // Exceptions from a lowe EL, AArch64
 #[no_mangle]
 extern "C" fn lower_el_aarch64_sync(e: &mut ExceptionContext) {
-    default_exception_handler(e)
+    dispatch_sync(VectorGroup::LowerAArch64, e)
 }
 #[no_mangle]
 extern "C" fn lower_el_aarch64_irq(e: &mut ExceptionContext) {
-    default_exception_handler(e)
+    dispatch(VectorGroup::LowerAArch64, ExceptionKind::Irq, e)
 }
 #[no_mangle]
 extern "C" fn lower_el_aarch64_fiq(e: &mut ExceptionContext) {
-    default_exception_handler(e)
+    dispatch(VectorGroup::LowerAArch64, ExceptionKind::Fiq, e)
 }
 #[no_mangle]
 extern "C" fn lower_el_aarch64_serror(e: &mut ExceptionContext) {
-    default_exception_handler(e)
+    dispatch(VectorGroup::LowerAArch64, ExceptionKind::SError, e)
 }
 
 // Exceptions from a lowe EL, AArch32
 // These are probably impossible
 #[no_mangle]
 extern "C" fn lower_el_aarch32_sync(e: &mut ExceptionContext) {
-    default_exception_handler(e)
+    dispatch_sync(VectorGroup::LowerAArch32, e)
 }
 #[no_mangle]
 extern "C" fn lower_el_aarch32_irq(e: &mut ExceptionContext) {
-    default_exception_handler(e)
+    dispatch(VectorGroup::LowerAArch32, ExceptionKind::Irq, e)
 }
 #[no_mangle]
 extern "C" fn lower_el_aarch32_fiq(e: &mut ExceptionContext) {
-    default_exception_handler(e)
+    dispatch(VectorGroup::LowerAArch32, ExceptionKind::Fiq, e)
 }
 #[no_mangle]
 extern "C" fn lower_el_aarch32_serror(e: &mut ExceptionContext) {
-    default_exception_handler(e)
+    dispatch(VectorGroup::LowerAArch32, ExceptionKind::SError, e)
 }
@@ -0,0 +1,151 @@
+use aarch64_cpu::registers::ESR_EL1;
+use core::fmt::Display;
+use tock_registers::{interfaces::Readable, register_bitfields, registers::InMemoryRegister};
+
+register_bitfields! {u64,
+    /// ISS encoding for instruction and data aborts
+    ISS_ABORT [
+        /// FAR is not valid and holds an UNKNOWN value
+        FnV OFFSET(10) NUMBITS(1),
+
+        /// Fault came from a cache maintenance or address translation instruction
+        CM OFFSET(8) NUMBITS(1),
+
+        /// Fault happened on a stage 2 translation during a stage 1 translation table walk
+        S1PTW OFFSET(7) NUMBITS(1),
+
+        /// Data aborts only. Set if the fault was caused by a write
+        WnR OFFSET(6) NUMBITS(1),
+
+        /// Data fault status code for data aborts, instruction fault status code for instruction
+        /// aborts
+        FSC OFFSET(0) NUMBITS(6),
+    ],
+
+    /// ISS encoding for SVC, HVC and SMC
+    ISS_CALL [
+        IMM16 OFFSET(0) NUMBITS(16),
+    ],
+
+    /// ISS encoding for BRK
+    ISS_BRK [
+        COMMENT OFFSET(0) NUMBITS(16),
+    ],
+}
+
+/// Human readable description of an exception syndrome
+pub struct Syndrome {
+    ec: Option<ESR_EL1::EC::Value>,
+    iss: u64,
+}
+
+impl Syndrome {
+    pub fn new(esr: &InMemoryRegister<u64, ESR_EL1::Register>) -> Self {
+        Self {
+            ec: esr.read_as_enum(ESR_EL1::EC),
+            iss: esr.read(ESR_EL1::ISS),
+        }
+    }
+}
+
+impl Display for Syndrome {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use ESR_EL1::EC::Value::*;
+
+        let Some(ec) = self.ec else {
+            return write!(f, "unknown exception class");
+        };
+
+        match ec {
+            Unknown => write!(f, "unknown reason, probably an undefined instruction"),
+            TrappedWFIorWFE => write!(f, "trapped WFI or WFE"),
+            TrappedFP | TrappedFP64 => write!(f, "trapped floating point access"),
+            IllegalExecutionState => write!(f, "illegal execution state"),
+            SVC64 => self.fmt_call(f, "SVC"),
+            HVC64 => self.fmt_call(f, "HVC"),
+            SMC64 => self.fmt_call(f, "SMC"),
+            TrappedMsrMrs => write!(f, "trapped system register access"),
+            InstrAbortLowerEL | InstrAbortCurrentEL => self.fmt_abort(f, false),
+            DataAbortLowerEL | DataAbortCurrentEL => self.fmt_abort(f, true),
+            PCAlignmentFault => write!(f, "PC alignment fault"),
+            SPAlignmentFault => write!(f, "SP alignment fault"),
+            SError => write!(f, "SError interrupt"),
+            BreakpointLowerEL | BreakpointCurrentEL => write!(f, "hardware breakpoint"),
+            SoftwareStepLowerEL | SoftwareStepCurrentEL => write!(f, "software step"),
+            WatchpointLowerEL | WatchpointCurrentEL => write!(f, "watchpoint"),
+            Brk64 => write!(
+                f,
+                "BRK #{:#X}",
+                self.iss_reg::<ISS_BRK::Register>().read(ISS_BRK::COMMENT)
+            ),
+            _ => write!(f, "AArch32 or otherwise unsupported exception"),
+        }
+    }
+}
+
+impl Syndrome {
+    fn iss_reg<R: tock_registers::RegisterLongName>(&self) -> InMemoryRegister<u64, R> {
+        InMemoryRegister::new(self.iss)
+    }
+
+    fn fmt_call(&self, f: &mut core::fmt::Formatter<'_>, instr: &str) -> core::fmt::Result {
+        let imm = self.iss_reg::<ISS_CALL::Register>().read(ISS_CALL::IMM16);
+        write!(f, "{} #{:#X}", instr, imm)
+    }
+
+    fn fmt_abort(&self, f: &mut core::fmt::Formatter<'_>, data: bool) -> core::fmt::Result {
+        let iss = self.iss_reg::<ISS_ABORT::Register>();
+
+        fmt_fault_status(f, iss.read(ISS_ABORT::FSC))?;
+
+        if !data {
+            write!(f, " on instruction fetch")?;
+        } else if iss.is_set(ISS_ABORT::CM) {
+            write!(f, " on cache maintenance")?;
+        } else if iss.is_set(ISS_ABORT::WnR) {
+            write!(f, " on write")?;
+        } else {
+            write!(f, " on read")?;
+        }
+
+        if iss.is_set(ISS_ABORT::S1PTW) {
+            write!(f, " during stage 1 translation table walk")?;
+        }
+        if iss.is_set(ISS_ABORT::FnV) {
+            write!(f, " (FAR not valid)")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Decode a DFSC/IFSC value
+fn fmt_fault_status(f: &mut core::fmt::Formatter<'_>, fsc: u64) -> core::fmt::Result {
+    let level = fsc & 0b11;
+
+    match fsc >> 2 {
+        0b0000 => return write!(f, "level {} address size fault", level),
+        0b0001 => return write!(f, "level {} translation fault", level),
+        0b0010 => return write!(f, "level {} access flag fault", level),
+        0b0011 => return write!(f, "level {} permission fault", level),
+        0b0101 => {
+            return write!(
+                f,
+                "level {} synchronous external abort on table walk",
+                level
+            )
+        }
+        0b0111 => return write!(f, "level {} parity or ECC error on table walk", level),
+        _ => (),
+    }
+
+    match fsc {
+        0b01_0000 => write!(f, "synchronous external abort"),
+        0b01_1000 => write!(f, "parity or ECC error"),
+        0b10_0001 => write!(f, "alignment fault"),
+        0b11_0000 => write!(f, "TLB conflict abort"),
+        0b11_0001 => write!(f, "unsupported atomic hardware update fault"),
+        0b11_0101 => write!(f, "unsupported exclusive or atomic access"),
+        _ => write!(f, "unknown fault status {:#04X}", fsc),
+    }
+}
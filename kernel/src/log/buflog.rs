@@ -1,4 +1,4 @@
-use crate::{sync::NullLock, time};
+use crate::{sync::IRQSafeLock, time};
 
 use super::{LogLevel, LogWrite};
 use core::fmt::Write;
@@ -78,13 +78,13 @@ impl Write for BufLoggerInner {
 }
 
 pub struct BufLogger {
-    inner: NullLock<BufLoggerInner>,
+    inner: IRQSafeLock<BufLoggerInner>,
 }
 
 impl BufLogger {
     pub const fn new() -> Self {
         Self {
-            inner: NullLock::new(BufLoggerInner::new()),
+            inner: IRQSafeLock::new(BufLoggerInner::new()),
         }
     }
 
@@ -92,6 +92,35 @@ impl BufLogger {
         self.inner.lock(|i| i.writer = Some(w))
     }
 
+    /// Release the logger and writer locks, so a panic that hit while one was held can still
+    /// print. Other cores must already have been told to stop
+    pub unsafe fn force_unlock(&self) {
+        self.inner.force_unlock();
+        if let Some(w) = self.inner.lock(|i| i.writer) {
+            w.force_unlock();
+        }
+    }
+
+    /// Switch the writer to synchronous output and push out everything still buffered
+    pub fn set_sync(&self) {
+        self.inner.lock(|i| {
+            if let Some(w) = i.writer {
+                w.set_sync();
+            }
+            i.flush();
+        })
+    }
+
+    /// Write out everything buffered and wait for the writer to finish sending it
+    pub fn flush(&self) {
+        self.inner.lock(|i| {
+            i.flush();
+            if let Some(w) = i.writer {
+                w.flush();
+            }
+        })
+    }
+
     pub fn log(&self, level: LogLevel, args: core::fmt::Arguments) {
         self.inner.lock(|i| {
             let uptime = time::uptime();
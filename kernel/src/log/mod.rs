@@ -24,6 +24,16 @@ impl core::fmt::Display for LogLevel {
 
 pub trait LogWrite {
     fn write_str(&self, s: &str);
+
+    /// Block until everything written so far has left the device
+    fn flush(&self);
+
+    /// Make every following write synchronous. Used once interrupts can no longer be relied on,
+    /// for example after a panic
+    fn set_sync(&self);
+
+    /// Release the writer's lock in case the panicking code held it
+    unsafe fn force_unlock(&self);
 }
 
 static LOGGER: BufLogger = BufLogger::new();
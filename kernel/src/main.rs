@@ -3,49 +3,136 @@
 #![no_std]
 #![no_main]
 
+extern crate alloc;
+
 use core::time::Duration;
 
 use exception::current_el;
 use memory::mmu;
 
+mod backtrace;
 mod boot;
+mod cpu;
 mod driver;
 mod exception;
 mod log;
 mod memory;
 mod panic;
+mod percpu;
+mod smp;
+mod symbols;
 mod sync;
+mod syscall;
+mod task;
 mod time;
 
 unsafe fn kernel_init() -> ! {
     #[cfg(feature = "debug_wait")]
     core::arch::asm!("1:", "wfe", "b 1b");
 
+    // Still at the physical load address, where nothing that goes through a pointer stored in
+    // memory works. Nothing can report an error yet either
+    let _ = mmu::enable(kernel_init_high);
+    cpu::wait_forever()
+}
+
+unsafe fn kernel_init_high() -> ! {
+    // Locks need the MMU and caches for exclusive accesses, so this comes before anything that
+    // takes one
+    mmu::switch_to_kernel_tables().unwrap();
+    percpu::init();
+    memory::heap().init();
+    memory::frame_allocator().init();
     exception::init_handlers();
-    mmu::enable().unwrap();
+    syscall::init().unwrap();
+    task::init().unwrap();
 
     driver::setup_drivers();
     driver::manager().init();
+    smp::init().unwrap();
+    exception::irq::unmask();
+
+    smp::start_secondary_cores();
 
     kernel_start()
 }
 
+unsafe fn secondary_init() -> ! {
+    let _ = mmu::enable_secondary(secondary_init_high);
+    cpu::wait_forever()
+}
+
+unsafe fn secondary_init_high() -> ! {
+    mmu::unmap_trampoline();
+    percpu::init();
+    exception::install_vectors();
+    smp::init_current_core();
+    exception::irq::unmask();
+
+    secondary_start()
+}
+
+fn secondary_start() -> ! {
+    info!("Core {} online", cpu::id());
+    smp::mark_online();
+
+    cpu::wait_forever()
+}
+
 fn kernel_start() -> ! {
     info!("Kernel started");
     info!("Current privilege level: {:?}", current_el());
 
     memory::print_kernel_memory_layout();
+    match mmu::find_writable_executable() {
+        Some(addr) => panic!("Page {:#X} is both writable and executable", addr),
+        None => info!("W^X check passed"),
+    }
+    info!("Physical frames: {}", memory::frame_allocator().stats());
+    info!("Kernel heap: {}", memory::heap().stats());
 
-    info!("Spinning for 1 seconds");
-    time::spin_for(Duration::from_secs(1));
+    info!("Sleeping for 1 seconds");
+    time::sleep_for(Duration::from_secs(1));
 
     info!("Trying to read from address 8 GiB...");
-    let big_addr: u64 = 8 * 1024 * 1024 * 1024;
-    unsafe { core::ptr::read_volatile(big_addr as *mut u64) };
+    let big_addr: usize = 8 * 1024 * 1024 * 1024;
+    match unsafe { memory::probe_read::<u64>(big_addr) } {
+        Ok(v) => info!("Read {:#X}", v),
+        Err(f) => warn!("Read failed: {}", f),
+    }
+
+    if let Some(frame) = memory::frame_allocator().alloc() {
+        let virt = memory::map::phys_to_virt(frame);
+        info!("Freeing frame {:#X} and reading from it...", frame);
+        memory::frame_allocator().free(frame).unwrap();
+        match unsafe { memory::probe_read::<u64>(virt) } {
+            Ok(v) => warn!("Read {:#X} from a freed frame", v),
+            Err(f) => info!("Read failed: {}", f),
+        }
+    }
+
+    let msg = "Hello from a system call\n";
+    if let Err(e) = syscall!(syscall::nr::WRITE, msg.as_ptr(), msg.len()) {
+        warn!("write syscall failed: {:?}", e);
+    }
+
+    match task::user_image() {
+        Some(image) => {
+            info!("Running user program");
+            match task::load(image).and_then(|mut t| t.run()) {
+                Ok(exit) => info!("User program {}", exit),
+                Err(e) => warn!("Failed to run user program: {}", e),
+            }
+        }
+        None => info!("No user program built in"),
+    }
+
+    smp::call_on_all(&|| info!("Hello from core {}", cpu::id()));
 
     loop {
-        if let Some(c) = driver::UART_DRIVER.read_char() {
+        while let Some(c) = driver::UART_DRIVER.read_char() {
             info!("Read {}", c)
         }
+        cpu::wait_for_interrupt();
     }
 }
@@ -0,0 +1,243 @@
+use super::{
+    layout::{AccessPermissions, AttributeFields, MemAttributes},
+    map, mmu,
+};
+use crate::sync::IRQSafeLock;
+use core::{fmt::Display, ops::Range};
+
+pub const FRAME_SIZE: usize = 64 * 1024;
+const MAX_FRAMES: usize = (map::END_INCLUSIVE + 1) / FRAME_SIZE;
+const WORDS: usize = MAX_FRAMES / 64;
+
+/// Free RAM is unmapped, frames get mapped at their linear address while they are allocated
+const FRAME_ATTRIBUTES: AttributeFields = AttributeFields {
+    mem_attributes: MemAttributes::CacheableDRAM,
+    acc_perms: AccessPermissions::ReadWrite,
+    execute_never: true,
+    el0_accessible: false,
+};
+
+#[derive(Copy, Clone, Debug)]
+pub struct FrameStats {
+    /// Frames of DRAM the allocator manages
+    pub total: usize,
+    /// Frames set aside at boot for the kernel image, the boot stack and firmware
+    pub reserved: usize,
+    pub allocated: usize,
+    pub free: usize,
+}
+
+impl Display for FrameStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mib = |frames: usize| frames * FRAME_SIZE / (1024 * 1024);
+        write!(
+            f,
+            "{} MiB total, {} MiB reserved, {} MiB allocated, {} MiB free",
+            mib(self.total),
+            mib(self.reserved),
+            mib(self.allocated),
+            mib(self.free)
+        )
+    }
+}
+
+struct FrameAllocatorInner {
+    /// One bit per frame of the physical address space, set if it can't be handed out
+    used: [u64; WORDS],
+    /// Set for frames handed out by `alloc_contiguous`, the only ones that may be freed
+    allocated: [u64; WORDS],
+    stats: FrameStats,
+}
+
+impl FrameAllocatorInner {
+    const fn new() -> Self {
+        Self {
+            used: [u64::MAX; WORDS],
+            allocated: [0; WORDS],
+            stats: FrameStats {
+                total: 0,
+                reserved: 0,
+                allocated: 0,
+                free: 0,
+            },
+        }
+    }
+
+    fn bit(bitmap: &[u64; WORDS], frame: usize) -> bool {
+        bitmap[frame / 64] & (1 << (frame % 64)) != 0
+    }
+
+    fn set_bit(bitmap: &mut [u64; WORDS], frame: usize, set: bool) {
+        if set {
+            bitmap[frame / 64] |= 1 << (frame % 64);
+        } else {
+            bitmap[frame / 64] &= !(1 << (frame % 64));
+        }
+    }
+
+    fn is_used(&self, frame: usize) -> bool {
+        Self::bit(&self.used, frame)
+    }
+
+    fn set_used(&mut self, frame: usize, used: bool) {
+        Self::set_bit(&mut self.used, frame, used);
+    }
+
+    /// Frames overlapping `range`, rounded outwards
+    fn frames(range: Range<usize>) -> Range<usize> {
+        (range.start / FRAME_SIZE)..range.end.div_ceil(FRAME_SIZE).min(MAX_FRAMES)
+    }
+
+    fn add_free(&mut self, range: Range<usize>) {
+        // Only whole frames are usable
+        let frames = range.start.div_ceil(FRAME_SIZE)..(range.end / FRAME_SIZE).min(MAX_FRAMES);
+        for frame in frames {
+            if self.is_used(frame) {
+                self.set_used(frame, false);
+                self.stats.total += 1;
+                self.stats.free += 1;
+            }
+        }
+    }
+
+    fn reserve(&mut self, range: Range<usize>) {
+        for frame in Self::frames(range) {
+            if !self.is_used(frame) {
+                self.set_used(frame, true);
+                self.stats.free -= 1;
+                self.stats.reserved += 1;
+            }
+        }
+    }
+
+    fn alloc_contiguous(&mut self, count: usize) -> Option<usize> {
+        if count == 0 || count > self.stats.free {
+            return None;
+        }
+
+        let mut run_start = 0;
+        let mut run_len = 0;
+        let mut frame = 0;
+        while frame < MAX_FRAMES {
+            // Skip over fully used words
+            if frame.is_multiple_of(64) && self.used[frame / 64] == u64::MAX {
+                run_len = 0;
+                frame += 64;
+                continue;
+            }
+
+            if self.is_used(frame) {
+                run_len = 0;
+            } else {
+                if run_len == 0 {
+                    run_start = frame;
+                }
+                run_len += 1;
+
+                if run_len == count {
+                    for f in run_start..run_start + count {
+                        self.set_used(f, true);
+                        Self::set_bit(&mut self.allocated, f, true);
+                    }
+                    self.stats.free -= count;
+                    self.stats.allocated += count;
+                    return Some(run_start * FRAME_SIZE);
+                }
+            }
+            frame += 1;
+        }
+
+        None
+    }
+
+    fn check_allocated(&self, addr: usize, count: usize) -> Result<(), &'static str> {
+        if !addr.is_multiple_of(FRAME_SIZE) {
+            return Err("Address is not frame aligned");
+        }
+
+        let first = addr / FRAME_SIZE;
+        if first + count > MAX_FRAMES {
+            return Err("Address out of range");
+        }
+        if (first..first + count).any(|f| !Self::bit(&self.allocated, f)) {
+            return Err("Frame is not allocated");
+        }
+
+        Ok(())
+    }
+
+    fn free_contiguous(&mut self, addr: usize, count: usize) -> Result<(), &'static str> {
+        self.check_allocated(addr, count)?;
+
+        let first = addr / FRAME_SIZE;
+        for frame in first..first + count {
+            self.set_used(frame, false);
+            Self::set_bit(&mut self.allocated, frame, false);
+        }
+        self.stats.free += count;
+        self.stats.allocated -= count;
+        Ok(())
+    }
+}
+
+/// Bitmap allocator for 64 KiB physical frames of DRAM
+pub struct FrameAllocator {
+    inner: IRQSafeLock<FrameAllocatorInner>,
+}
+
+#[allow(dead_code)]
+impl FrameAllocator {
+    pub const fn new() -> Self {
+        Self {
+            inner: IRQSafeLock::new(FrameAllocatorInner::new()),
+        }
+    }
+
+    /// Hand out the DRAM in `map::dram`, except for everything the kernel and the firmware are
+    /// already using
+    pub fn init(&self) {
+        self.inner.lock(|i| {
+            i.add_free(map::dram::LOW_START..map::dram::LOW_END_INCLUSIVE + 1);
+            i.add_free(map::dram::HIGH_START..map::dram::HIGH_END_EXCLUSIVE);
+
+            let phys = map::virt_to_phys;
+            // Firmware spin tables and the boot core stack live below the kernel
+            i.reserve(phys(map::firmware_start())..phys(map::kernel_end_exclusive()));
+        })
+    }
+
+    /// Physical address of a free frame
+    pub fn alloc(&self) -> Option<usize> {
+        self.alloc_contiguous(1)
+    }
+
+    /// Physical address of `count` free frames in a row. They are mapped at
+    /// `map::phys_to_virt(addr)` until freed
+    pub fn alloc_contiguous(&self, count: usize) -> Option<usize> {
+        let addr = self.inner.lock(|i| i.alloc_contiguous(count))?;
+
+        let len = count * FRAME_SIZE;
+        if mmu::map(map::phys_to_virt(addr), addr, len, FRAME_ATTRIBUTES).is_err() {
+            self.inner.lock(|i| i.free_contiguous(addr, count)).ok();
+            return None;
+        }
+
+        Some(addr)
+    }
+
+    pub fn free(&self, addr: usize) -> Result<(), &'static str> {
+        self.free_contiguous(addr, 1)
+    }
+
+    /// Give back frames from `alloc_contiguous`. Runs can also be freed in parts
+    pub fn free_contiguous(&self, addr: usize, count: usize) -> Result<(), &'static str> {
+        // Unmapped before the frames become free, so nobody gets a frame that is still mapped
+        self.inner.lock(|i| i.check_allocated(addr, count))?;
+        mmu::unmap(map::phys_to_virt(addr), count * FRAME_SIZE)?;
+        self.inner.lock(|i| i.free_contiguous(addr, count))
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        self.inner.lock(|i| i.stats)
+    }
+}
@@ -0,0 +1,193 @@
+use super::map;
+use crate::sync::IRQSafeLock;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    fmt::Display,
+    ptr,
+};
+
+/// Every block is a multiple of this, big enough to hold a free block header
+const BLOCK_ALIGN: usize = 16;
+
+#[derive(Copy, Clone, Debug)]
+pub struct HeapStats {
+    pub size: usize,
+    pub used: usize,
+    /// Highest `used` has ever been
+    pub peak: usize,
+    /// Live allocations
+    pub allocations: usize,
+    pub failed: usize,
+}
+
+impl Display for HeapStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} KiB used of {} KiB (peak {} KiB), {} allocations, {} failed",
+            self.used / 1024,
+            self.size / 1024,
+            self.peak / 1024,
+            self.allocations,
+            self.failed
+        )
+    }
+}
+
+/// Header written at the start of every free block
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+struct KernelHeapInner {
+    /// Free blocks, sorted by address so neighbours can be merged
+    free: *mut FreeBlock,
+    stats: HeapStats,
+}
+
+/// Only ever touched with the lock held
+unsafe impl Send for KernelHeapInner {}
+
+impl KernelHeapInner {
+    const fn new() -> Self {
+        Self {
+            free: ptr::null_mut(),
+            stats: HeapStats {
+                size: 0,
+                used: 0,
+                peak: 0,
+                allocations: 0,
+                failed: 0,
+            },
+        }
+    }
+
+    fn block_size(layout: &Layout) -> usize {
+        layout.size().max(1).next_multiple_of(BLOCK_ALIGN)
+    }
+
+    /// First fit. Leftovers before and after the allocation stay in the list, they are always
+    /// either empty or big enough for a header since everything is a multiple of BLOCK_ALIGN
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let size = Self::block_size(&layout);
+        let align = layout.align().max(BLOCK_ALIGN);
+
+        let mut link: *mut *mut FreeBlock = &mut self.free;
+        while !(*link).is_null() {
+            let block = *link;
+            let block_start = block as usize;
+            let block_end = block_start + (*block).size;
+
+            let start = block_start.next_multiple_of(align);
+            let end = start + size;
+            if end > block_end {
+                link = &mut (*block).next;
+                continue;
+            }
+
+            let mut next = (*block).next;
+            if end < block_end {
+                let tail = end as *mut FreeBlock;
+                tail.write(FreeBlock {
+                    size: block_end - end,
+                    next,
+                });
+                next = tail;
+            }
+            if start > block_start {
+                (*block).size = start - block_start;
+                (*block).next = next;
+            } else {
+                *link = next;
+            }
+
+            self.stats.used += size;
+            self.stats.peak = self.stats.peak.max(self.stats.used);
+            self.stats.allocations += 1;
+            return start as *mut u8;
+        }
+
+        self.stats.failed += 1;
+        ptr::null_mut()
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let size = Self::block_size(&layout);
+        let start = ptr as usize;
+
+        // Find the free blocks around the one being freed
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut next = self.free;
+        while !next.is_null() && (next as usize) < start {
+            prev = next;
+            next = (*next).next;
+        }
+
+        let block = ptr as *mut FreeBlock;
+        block.write(FreeBlock { size, next });
+        if !next.is_null() && start + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+
+        if prev.is_null() {
+            self.free = block;
+        } else if prev as usize + (*prev).size == start {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        } else {
+            (*prev).next = block;
+        }
+
+        self.stats.used -= size;
+        self.stats.allocations -= 1;
+    }
+
+    unsafe fn add_region(&mut self, start: usize, end: usize) {
+        let start = start.next_multiple_of(BLOCK_ALIGN);
+        let end = end & !(BLOCK_ALIGN - 1);
+
+        let block = start as *mut FreeBlock;
+        block.write(FreeBlock {
+            size: end - start,
+            next: self.free,
+        });
+        self.free = block;
+        self.stats.size += end - start;
+    }
+}
+
+/// Linked list allocator over the heap section reserved in the linker script
+pub struct KernelHeap {
+    inner: IRQSafeLock<KernelHeapInner>,
+}
+
+impl KernelHeap {
+    pub const fn new() -> Self {
+        Self {
+            inner: IRQSafeLock::new(KernelHeapInner::new()),
+        }
+    }
+
+    pub unsafe fn init(&self) {
+        self.inner
+            .lock(|i| i.add_region(map::heap_start(), map::heap_end_exclusive()));
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        self.inner.lock(|i| i.stats)
+    }
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    /// Failures are only counted in the stats. Logging here could re-enter the logger if it was
+    /// the one allocating
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.inner.lock(|i| i.alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.lock(|i| i.dealloc(ptr, layout))
+    }
+}
@@ -1,27 +1,31 @@
 use core::range::RangeInclusive;
 
 #[derive(Copy, Clone)]
-pub(super) enum MemAttributes {
+pub enum MemAttributes {
     CacheableDRAM,
     Device,
 }
 
 #[derive(Copy, Clone)]
-pub(super) enum AccessPermissions {
+pub enum AccessPermissions {
     ReadOnly,
     ReadWrite,
 }
 
 #[derive(Copy, Clone)]
-pub(super) struct AttributeFields {
+pub struct AttributeFields {
     pub mem_attributes: MemAttributes,
     pub acc_perms: AccessPermissions,
+    /// Not executable at the EL the page belongs to
     pub execute_never: bool,
+    /// User page. Accessible from EL0 and never executable from EL1
+    pub el0_accessible: bool,
 }
 
 struct TranslationDescriptor {
     pub name: &'static str,
     pub virtual_range: fn() -> RangeInclusive<usize>,
+    /// Physical start of the range, if it isn't the linear mapping at `map::VIRT_OFFSET`
     pub map_to: Option<usize>,
     pub attribute_fields: AttributeFields,
 }
@@ -33,10 +37,11 @@ pub(super) struct KernelVirtualLayout<const LAYOUTS: usize> {
 }
 
 impl<const LAYOUTS: usize> KernelVirtualLayout<LAYOUTS> {
+    /// Output address and attributes of `virt_addr`, `None` if it is left unmapped
     pub fn virt_addr_props(
         &self,
         virt_addr: usize,
-    ) -> Result<(usize, AttributeFields), &'static str> {
+    ) -> Result<Option<(usize, AttributeFields)>, &'static str> {
         if virt_addr > self.max_virt_addr {
             return Err("Address out of range");
         }
@@ -45,57 +50,174 @@ impl<const LAYOUTS: usize> KernelVirtualLayout<LAYOUTS> {
             if (i.virtual_range)().contains(&virt_addr) {
                 let output_addr = match i.map_to {
                     Some(start) => start + (virt_addr - (i.virtual_range)().start),
-                    None => virt_addr,
+                    None => super::map::virt_to_phys(virt_addr),
                 };
 
-                return Ok((output_addr, i.attribute_fields));
+                return Ok(Some((output_addr, i.attribute_fields)));
             }
         }
 
-        Err("Address not mapped")
+        Ok(None)
     }
 }
 
-pub(super) static KERNEL_LAYOUT: KernelVirtualLayout<3> = KernelVirtualLayout {
-    max_virt_addr: super::map::END_INCLUSIVE,
+/// Everything the kernel maps. RAM that isn't listed is left unmapped, including the guards below
+/// the stacks. The frame allocator maps frames as it hands them out
+pub(super) static KERNEL_LAYOUT: KernelVirtualLayout<11> = KernelVirtualLayout {
+    max_virt_addr: super::map::phys_to_virt(super::map::END_INCLUSIVE),
 
     layouts: [
         TranslationDescriptor {
-            name: "Kernel code and RO data",
+            name: "Firmware",
             virtual_range: || RangeInclusive {
-                start: super::map::code_start(),
-                end: super::map::code_end_exclusize() - 1,
+                start: super::map::firmware_start(),
+                end: super::map::firmware_end_exclusive() - 1,
+            },
+            attribute_fields: AttributeFields {
+                mem_attributes: MemAttributes::CacheableDRAM,
+                acc_perms: AccessPermissions::ReadWrite,
+                execute_never: true,
+                el0_accessible: false,
+            },
+            map_to: None,
+        },
+        TranslationDescriptor {
+            name: "Boot core stack",
+            virtual_range: || RangeInclusive {
+                start: super::map::boot_core_stack_start(),
+                end: super::map::boot_core_stack_end_exclusive() - 1,
+            },
+            attribute_fields: AttributeFields {
+                mem_attributes: MemAttributes::CacheableDRAM,
+                acc_perms: AccessPermissions::ReadWrite,
+                execute_never: true,
+                el0_accessible: false,
+            },
+            map_to: None,
+        },
+        TranslationDescriptor {
+            name: "Kernel code",
+            virtual_range: || RangeInclusive {
+                start: super::map::text_start(),
+                end: super::map::text_end_exclusive() - 1,
             },
             attribute_fields: AttributeFields {
                 mem_attributes: MemAttributes::CacheableDRAM,
                 acc_perms: AccessPermissions::ReadOnly,
                 execute_never: false,
+                el0_accessible: false,
             },
             map_to: None,
         },
         TranslationDescriptor {
-            name: "Device MMIO",
+            name: "Kernel RO data",
             virtual_range: || RangeInclusive {
-                start: super::map::mmio::START,
-                end: super::map::mmio::END_INCLUSIVE,
+                start: super::map::rodata_start(),
+                end: super::map::rodata_end_exclusive() - 1,
             },
             attribute_fields: AttributeFields {
-                mem_attributes: MemAttributes::Device,
+                mem_attributes: MemAttributes::CacheableDRAM,
+                acc_perms: AccessPermissions::ReadOnly,
+                execute_never: true,
+                el0_accessible: false,
+            },
+            map_to: None,
+        },
+        TranslationDescriptor {
+            name: "Kernel data and bss",
+            virtual_range: || RangeInclusive {
+                start: super::map::data_start(),
+                end: super::map::data_end_exclusive() - 1,
+            },
+            attribute_fields: AttributeFields {
+                mem_attributes: MemAttributes::CacheableDRAM,
+                acc_perms: AccessPermissions::ReadWrite,
+                execute_never: true,
+                el0_accessible: false,
+            },
+            map_to: None,
+        },
+        TranslationDescriptor {
+            name: "Emergency stacks",
+            virtual_range: || RangeInclusive {
+                start: super::map::emergency_stacks_start(),
+                end: super::map::emergency_stacks_end_exclusive() - 1,
+            },
+            attribute_fields: AttributeFields {
+                mem_attributes: MemAttributes::CacheableDRAM,
                 acc_perms: AccessPermissions::ReadWrite,
                 execute_never: true,
+                el0_accessible: false,
             },
             map_to: None,
         },
         TranslationDescriptor {
-            name: "Other memory",
+            name: "Core 1 stack",
             virtual_range: || RangeInclusive {
-                start: 0,
-                end: super::map::END_INCLUSIVE,
+                start: super::map::secondary_stack_start(1),
+                end: super::map::secondary_stack_end_exclusive(1) - 1,
             },
             attribute_fields: AttributeFields {
                 mem_attributes: MemAttributes::CacheableDRAM,
                 acc_perms: AccessPermissions::ReadWrite,
                 execute_never: true,
+                el0_accessible: false,
+            },
+            map_to: None,
+        },
+        TranslationDescriptor {
+            name: "Core 2 stack",
+            virtual_range: || RangeInclusive {
+                start: super::map::secondary_stack_start(2),
+                end: super::map::secondary_stack_end_exclusive(2) - 1,
+            },
+            attribute_fields: AttributeFields {
+                mem_attributes: MemAttributes::CacheableDRAM,
+                acc_perms: AccessPermissions::ReadWrite,
+                execute_never: true,
+                el0_accessible: false,
+            },
+            map_to: None,
+        },
+        TranslationDescriptor {
+            name: "Core 3 stack",
+            virtual_range: || RangeInclusive {
+                start: super::map::secondary_stack_start(3),
+                end: super::map::secondary_stack_end_exclusive(3) - 1,
+            },
+            attribute_fields: AttributeFields {
+                mem_attributes: MemAttributes::CacheableDRAM,
+                acc_perms: AccessPermissions::ReadWrite,
+                execute_never: true,
+                el0_accessible: false,
+            },
+            map_to: None,
+        },
+        TranslationDescriptor {
+            name: "Kernel heap",
+            virtual_range: || RangeInclusive {
+                start: super::map::heap_start(),
+                end: super::map::heap_end_exclusive() - 1,
+            },
+            attribute_fields: AttributeFields {
+                mem_attributes: MemAttributes::CacheableDRAM,
+                acc_perms: AccessPermissions::ReadWrite,
+                execute_never: true,
+                el0_accessible: false,
+            },
+            map_to: None,
+        },
+        TranslationDescriptor {
+            name: "Device MMIO",
+            virtual_range: || RangeInclusive {
+                start: super::map::phys_to_virt(super::map::mmio::START),
+                end: super::map::phys_to_virt(super::map::mmio::END_INCLUSIVE),
+            },
+            attribute_fields: AttributeFields {
+                mem_attributes: MemAttributes::Device,
+                acc_perms: AccessPermissions::ReadWrite,
+                execute_never: true,
+                el0_accessible: false,
             },
             map_to: None,
         },
@@ -139,15 +261,19 @@ impl core::fmt::Display for TranslationDescriptor {
             AccessPermissions::ReadWrite => "RW",
         };
 
-        let execute = if self.attribute_fields.execute_never {
-            "PXN"
-        } else {
-            "PX"
+        let execute = match (
+            self.attribute_fields.el0_accessible,
+            self.attribute_fields.execute_never,
+        ) {
+            (false, true) => "PXN",
+            (false, false) => "PX",
+            (true, true) => "UXN",
+            (true, false) => "UX",
         };
 
         write!(
             f,
-            "{:28}: {:#010X} - {:#010X} | {:3} {} | {} {} {}",
+            "{:28}: {:#018X} - {:#018X} | {:3} {} | {} {} {}",
             self.name, start, end, size, unit, attr, access, execute
         )
     }
@@ -1,26 +1,170 @@
 use core::cell::UnsafeCell;
 
 extern "Rust" {
-    static __code_start: UnsafeCell<()>;
-    static __code_end: UnsafeCell<()>;
+    static __text_start: UnsafeCell<()>;
+    static __text_end: UnsafeCell<()>;
+    static __rodata_start: UnsafeCell<()>;
+    static __rodata_end: UnsafeCell<()>;
+    static __data_start: UnsafeCell<()>;
+    static __data_end: UnsafeCell<()>;
+    static __firmware_start: UnsafeCell<()>;
+    static __firmware_end: UnsafeCell<()>;
+    static __emergency_stacks_start: UnsafeCell<()>;
+    static __emergency_stacks_end: UnsafeCell<()>;
+    static __secondary_stacks_start: UnsafeCell<()>;
+    static __boot_core_stack_start: UnsafeCell<()>;
+    static __boot_core_stack_end: UnsafeCell<()>;
+    static __heap_start: UnsafeCell<()>;
+    static __heap_end: UnsafeCell<()>;
+    static __kernel_end: UnsafeCell<()>;
 }
 
+/// Size of the physical address space, all of which is mapped at `VIRT_OFFSET`
 pub(super) const END_INCLUSIVE: usize = 0xFFFF_FFFF;
 
+/// Start of the kernel's higher half, where physical memory is mapped linearly. Has to match
+/// `VIRT_OFFSET` in link.ld
+pub const VIRT_OFFSET: usize = 0xFFFF_FFFF_0000_0000;
+
+/// RAM the ARM cores get. The firmware keeps the top of the first GiB for the VideoCore (gpu_mem,
+/// 76 MiB by default), RAM above that belongs to the ARM cores up to where MMIO starts. The board's
+/// RAM size comes from the `ram_2g`/`ram_4g` features
+pub mod dram {
+    #[cfg(not(any(feature = "ram_2g", feature = "ram_4g")))]
+    const SIZE: usize = 0x4000_0000;
+    #[cfg(all(feature = "ram_2g", not(feature = "ram_4g")))]
+    const SIZE: usize = 0x8000_0000;
+    #[cfg(feature = "ram_4g")]
+    const SIZE: usize = 0x1_0000_0000;
+
+    /// Above this addresses go to peripherals even if RAM is there
+    const MMIO_HOLE: usize = 0xFC00_0000;
+
+    pub const LOW_START: usize = 0;
+    pub const LOW_END_INCLUSIVE: usize = 0x3B3F_FFFF;
+
+    /// Empty on 1 GiB boards
+    pub const HIGH_START: usize = 0x4000_0000;
+    pub const HIGH_END_EXCLUSIVE: usize = if SIZE < MMIO_HOLE { SIZE } else { MMIO_HOLE };
+}
+
+/// Physical addresses
 pub mod mmio {
     pub const START: usize = 0xFE00_0000;
     pub const END_INCLUSIVE: usize = 0xFF84_FFFF;
 
     pub const GPIO_START: usize = START + 0x20_0000;
     pub const UART0_START: usize = START + 0x20_1000;
+
+    pub const GICD_START: usize = 0xFF84_1000;
+    pub const GICC_START: usize = 0xFF84_2000;
+}
+
+/// Every kernel stack sits in the lower half of a block aligned to twice this, with an unmapped
+/// guard below it. Has to match `KERNEL_STACK_SIZE` in link.ld
+pub const KERNEL_STACK_SIZE: usize = 128 * 1024;
+/// Per core, has to match `EMERGENCY_STACK_SIZE` in link.ld
+pub const EMERGENCY_STACK_SIZE: usize = 16 * 1024;
+
+/// Higher half alias of a physical address
+#[inline(always)]
+pub const fn phys_to_virt(phys_addr: usize) -> usize {
+    phys_addr + VIRT_OFFSET
+}
+
+/// Physical address of a higher half address. Only valid once the kernel runs from the higher
+/// half, before that symbols already resolve to physical addresses
+#[inline(always)]
+pub const fn virt_to_phys(virt_addr: usize) -> usize {
+    virt_addr - VIRT_OFFSET
+}
+
+#[inline(always)]
+pub(super) fn text_start() -> usize {
+    unsafe { __text_start.get() as usize }
+}
+
+#[inline(always)]
+pub(super) fn text_end_exclusive() -> usize {
+    unsafe { __text_end.get() as usize }
+}
+
+/// Read only data, including the fixup and symbol tables
+#[inline(always)]
+pub(super) fn rodata_start() -> usize {
+    unsafe { __rodata_start.get() as usize }
+}
+
+#[inline(always)]
+pub(super) fn rodata_end_exclusive() -> usize {
+    unsafe { __rodata_end.get() as usize }
+}
+
+/// Data, bss and the per-core areas
+#[inline(always)]
+pub(super) fn data_start() -> usize {
+    unsafe { __data_start.get() as usize }
+}
+
+#[inline(always)]
+pub(super) fn data_end_exclusive() -> usize {
+    unsafe { __data_end.get() as usize }
+}
+
+/// Left to the firmware's armstub and spin tables
+#[inline(always)]
+pub(super) fn firmware_start() -> usize {
+    unsafe { __firmware_start.get() as usize }
+}
+
+#[inline(always)]
+pub(super) fn firmware_end_exclusive() -> usize {
+    unsafe { __firmware_end.get() as usize }
+}
+
+#[inline(always)]
+pub(super) fn emergency_stacks_start() -> usize {
+    unsafe { __emergency_stacks_start.get() as usize }
+}
+
+#[inline(always)]
+pub(super) fn emergency_stacks_end_exclusive() -> usize {
+    unsafe { __emergency_stacks_end.get() as usize }
+}
+
+/// Stack of secondary core `core`, right above its guard
+#[inline(always)]
+pub fn secondary_stack_start(core: usize) -> usize {
+    secondary_stack_end_exclusive(core) - KERNEL_STACK_SIZE
+}
+
+#[inline(always)]
+pub fn secondary_stack_end_exclusive(core: usize) -> usize {
+    unsafe { __secondary_stacks_start.get() as usize + core * 2 * KERNEL_STACK_SIZE }
+}
+
+#[inline(always)]
+pub fn boot_core_stack_start() -> usize {
+    unsafe { __boot_core_stack_start.get() as usize }
+}
+
+#[inline(always)]
+pub fn boot_core_stack_end_exclusive() -> usize {
+    unsafe { __boot_core_stack_end.get() as usize }
+}
+
+#[inline(always)]
+pub(super) fn heap_start() -> usize {
+    unsafe { __heap_start.get() as usize }
 }
 
 #[inline(always)]
-pub(super) fn code_start() -> usize {
-    unsafe { __code_start.get() as usize }
+pub(super) fn heap_end_exclusive() -> usize {
+    unsafe { __heap_end.get() as usize }
 }
 
+/// End of everything the kernel image occupies, including the uninitialized sections
 #[inline(always)]
-pub(super) fn code_end_exclusize() -> usize {
-    unsafe { __code_end.get() as usize }
+pub(super) fn kernel_end_exclusive() -> usize {
+    unsafe { __kernel_end.get() as usize }
 }
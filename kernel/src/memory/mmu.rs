@@ -1,6 +1,16 @@
-use super::translation_table::KERNEL_TABLES;
+use super::{
+    layout::{AccessPermissions, AttributeFields},
+    map,
+    translation_table::{BOOT_TABLE, EMPTY_TABLE, KERNEL_TABLES},
+};
+use crate::sync::IRQSafeLock;
 use aarch64_cpu::{asm::barrier, registers::*};
 
+pub const PAGE_SIZE: usize = 64 * 1024;
+
+/// Serializes changes to `KERNEL_TABLES` after boot
+static MAPPING_LOCK: IRQSafeLock<()> = IRQSafeLock::new(());
+
 #[allow(dead_code)]
 #[derive(Debug)]
 pub enum MMUEnableError {
@@ -9,22 +19,34 @@ pub enum MMUEnableError {
     Other(&'static str),
 }
 
-pub unsafe fn enable() -> Result<(), MMUEnableError> {
+/// Turn on the MMU with the boot tables and continue at the higher half alias of `entry`, with
+/// the stack moved along. Called on the boot core at the physical load address with the MMU off,
+/// where only PC relative addressing works. Only returns if the MMU can't be turned on
+pub unsafe fn enable(entry: unsafe fn() -> !) -> MMUEnableError {
+    enable_and_jump(BOOT_TABLE.base_addr(), entry)
+}
+
+/// Same as `enable` for the secondary cores, which go straight to the kernel tables the boot core
+/// populated with `switch_to_kernel_tables`
+pub unsafe fn enable_secondary(entry: unsafe fn() -> !) -> MMUEnableError {
+    let tables = &raw const KERNEL_TABLES;
+    enable_and_jump((*tables).base_addr(), entry)
+}
+
+unsafe fn enable_and_jump(ttbr1_baddr: u64, entry: unsafe fn() -> !) -> MMUEnableError {
     if is_enabled() {
-        return Err(MMUEnableError::AlreadyEnabled);
+        return MMUEnableError::AlreadyEnabled;
     }
 
     if !ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran64::Supported) {
-        return Err(MMUEnableError::Granule64KNotSupported);
+        return MMUEnableError::Granule64KNotSupported;
     }
 
     setup_mair();
 
-    KERNEL_TABLES
-        .populate()
-        .map_err(|e| MMUEnableError::Other(e))?;
-
-    TTBR0_EL1.set_baddr(KERNEL_TABLES.phys_base_addr());
+    // The identity map in TTBR0 is the trampoline that keeps the next instruction fetch working
+    TTBR0_EL1.set_baddr(BOOT_TABLE.base_addr());
+    TTBR1_EL1.set_baddr(ttbr1_baddr);
 
     configure_tcr();
 
@@ -32,16 +54,205 @@ pub unsafe fn enable() -> Result<(), MMUEnableError> {
     SCTLR_EL1.write(SCTLR_EL1::M::Enable + SCTLR_EL1::C::Cacheable + SCTLR_EL1::I::Cacheable);
     barrier::isb(barrier::SY);
 
+    // Frame records below this point have physical addresses, end the chain here
+    core::arch::asm!(
+        "add sp, sp, x0",
+        "mov x29, xzr",
+        "mov x30, xzr",
+        "br x1",
+        in("x0") map::VIRT_OFFSET,
+        in("x1") map::phys_to_virt(entry as usize),
+        options(noreturn)
+    )
+}
+
+/// Populate the kernel tables from `KERNEL_LAYOUT` and move TTBR1 over to them from the boot
+/// tables, then drop the identity map. Called on the boot core once it runs from the higher half
+pub unsafe fn switch_to_kernel_tables() -> Result<(), MMUEnableError> {
+    let tables = &raw mut KERNEL_TABLES;
+    (*tables).populate().map_err(MMUEnableError::Other)?;
+
+    // Nothing in the higher half may be touched while TTBR1 changes, so this runs from the
+    // identity mapped alias of the same code
+    core::arch::asm!(
+        "adr {tmp}, 1f",
+        "sub {tmp}, {tmp}, {offset}",
+        "br {tmp}",
+        "1:",
+        "msr TTBR1_EL1, {baddr}",
+        "isb",
+        "tlbi vmalle1",
+        "dsb nsh",
+        "isb",
+        "adr {tmp}, 2f",
+        "add {tmp}, {tmp}, {offset}",
+        "br {tmp}",
+        "2:",
+        tmp = out(reg) _,
+        offset = in(reg) map::VIRT_OFFSET,
+        baddr = in(reg) (*tables).phys_base_addr(),
+        options(nostack)
+    );
+
+    unmap_trampoline();
     Ok(())
 }
 
+/// Replace the identity map used to turn on the MMU with the empty kernel TTBR0 tables. Its
+/// entries are global and would shadow user mappings otherwise
+pub unsafe fn unmap_trampoline() {
+    activate_kernel_tables();
+    core::arch::asm!("tlbi vmalle1", "dsb nsh", "isb", options(nostack));
+}
+
+/// Switch TTBR0 back to the kernel's empty tables after running in a user address space
+pub unsafe fn activate_kernel_tables() {
+    set_ttbr0(
+        map::virt_to_phys(EMPTY_TABLE.base_addr() as usize) as u64,
+        0,
+    );
+}
+
+/// ASID 0 belongs to the kernel's empty tables. Kernel pages are global and live in TTBR1, so
+/// switching doesn't need a TLB flush
+pub(super) unsafe fn set_ttbr0(baddr: u64, asid: u16) {
+    TTBR0_EL1.write(TTBR0_EL1::ASID.val(asid as u64) + TTBR0_EL1::BADDR.val(baddr >> 1));
+    barrier::isb(barrier::SY);
+}
+
+/// Drop every non-global TLB entry tagged with `asid`, on all cores
+pub(super) fn invalidate_asid(asid: u16) {
+    barrier::dsb(barrier::ISHST);
+    unsafe {
+        core::arch::asm!("tlbi aside1is, {}", in(reg) (asid as u64) << 48, options(nostack));
+    }
+    barrier::dsb(barrier::ISH);
+    barrier::isb(barrier::SY);
+}
+
+/// VA[55:12] field of a TLBI operand. The higher half sets the address bits above it, which
+/// would otherwise land in the TTL and ASID fields
+const TLBI_VA_MASK: u64 = (1 << 44) - 1;
+
+/// Drop the TLB entries for the page at `virt_addr` on all cores. Kernel pages are global, which
+/// `vae1is` matches regardless of ASID
+fn invalidate_page(virt_addr: usize) {
+    let operand = (virt_addr as u64 >> 12) & TLBI_VA_MASK;
+    unsafe {
+        core::arch::asm!("tlbi vae1is, {}", in(reg) operand, options(nostack));
+    }
+}
+
+/// Replace the descriptor of every page in `[virt_addr, virt_addr + len)` with what `new`
+/// returns for it, breaking valid mappings before making the new one
+fn update_pages(
+    virt_addr: usize,
+    len: usize,
+    new: impl Fn(usize, Option<usize>) -> Result<Option<(usize, AttributeFields)>, &'static str>,
+) -> Result<(), &'static str> {
+    if !virt_addr.is_multiple_of(PAGE_SIZE) || !len.is_multiple_of(PAGE_SIZE) {
+        return Err("Range is not page aligned");
+    }
+    let end = virt_addr.checked_add(len).ok_or("Range overflows")?;
+
+    MAPPING_LOCK.lock(|_| {
+        let tables = &raw mut KERNEL_TABLES;
+        let tables = unsafe { &mut *tables };
+
+        // Check the whole range first so a failure leaves the mappings untouched
+        for page in (virt_addr..end).step_by(PAGE_SIZE) {
+            new(page, tables.translate(page)?)?;
+        }
+
+        for page in (virt_addr..end).step_by(PAGE_SIZE) {
+            let old = tables.translate(page)?;
+            let new = new(page, old)?;
+
+            if old.is_some() {
+                tables.unmap_page(page)?;
+                barrier::dsb(barrier::ISHST);
+                invalidate_page(page);
+                barrier::dsb(barrier::ISH);
+            }
+            if let Some((output, attribs)) = new {
+                tables.map_page(page, output, attribs)?;
+            }
+        }
+
+        barrier::dsb(barrier::ISHST);
+        barrier::isb(barrier::SY);
+        Ok(())
+    })
+}
+
+/// Map `[virt_addr, virt_addr + len)` to `phys_addr` in the kernel tables, replacing whatever
+/// was mapped there. The range must not contain the code, stack or tables doing the mapping
+#[allow(dead_code)]
+pub fn map(
+    virt_addr: usize,
+    phys_addr: usize,
+    len: usize,
+    attribs: AttributeFields,
+) -> Result<(), &'static str> {
+    if !phys_addr.is_multiple_of(PAGE_SIZE) {
+        return Err("Physical address is not page aligned");
+    }
+    check_wx(&attribs)?;
+
+    update_pages(virt_addr, len, |page, _| {
+        Ok(Some((phys_addr + (page - virt_addr), attribs)))
+    })
+}
+
+/// Remove the mappings for `[virt_addr, virt_addr + len)`, accesses will fault afterwards
+#[allow(dead_code)]
+pub fn unmap(virt_addr: usize, len: usize) -> Result<(), &'static str> {
+    update_pages(virt_addr, len, |_, _| Ok(None))
+}
+
+/// Change the attributes of the already mapped pages in `[virt_addr, virt_addr + len)`
+#[allow(dead_code)]
+pub fn protect(virt_addr: usize, len: usize, attribs: AttributeFields) -> Result<(), &'static str> {
+    check_wx(&attribs)?;
+    update_pages(virt_addr, len, |_, old| match old {
+        Some(output) => Ok(Some((output, attribs))),
+        None => Err("Page not mapped"),
+    })
+}
+
+fn check_wx(attribs: &AttributeFields) -> Result<(), &'static str> {
+    match (attribs.acc_perms, attribs.execute_never) {
+        (AccessPermissions::ReadWrite, false) => Err("Mapping is both writable and executable"),
+        _ => Ok(()),
+    }
+}
+
+/// First page in the kernel tables that is both writable and executable. Nothing should ever be
+pub fn find_writable_executable() -> Option<usize> {
+    MAPPING_LOCK.lock(|_| {
+        let tables = &raw const KERNEL_TABLES;
+        unsafe { (*tables).find_writable_executable() }
+    })
+}
+
+/// Output address `virt_addr` is mapped to in the kernel tables
+#[allow(dead_code)]
+pub fn translate(virt_addr: usize) -> Result<Option<usize>, &'static str> {
+    MAPPING_LOCK.lock(|_| {
+        let tables = &raw mut KERNEL_TABLES;
+        let offset = virt_addr % PAGE_SIZE;
+        Ok(unsafe { (*tables).translate(virt_addr - offset)? }.map(|p| p + offset))
+    })
+}
+
 pub fn is_enabled() -> bool {
     SCTLR_EL1.matches_all(SCTLR_EL1::M::Enable)
 }
 
+/// Both halves cover 4 GiB: the low one for user address spaces, the high one for the kernel
 unsafe fn configure_tcr() {
-    let num_bits = (super::map::END_INCLUSIVE + 1).trailing_zeros();
-    let t0sz = (64 - num_bits) as u64;
+    let num_bits = (map::END_INCLUSIVE + 1).trailing_zeros();
+    let tsz = (64 - num_bits) as u64;
 
     TCR_EL1.write(
         TCR_EL1::IPS::Bits_40
@@ -49,9 +260,14 @@ unsafe fn configure_tcr() {
             + TCR_EL1::ORGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
             + TCR_EL1::SH0::Inner
             + TCR_EL1::TG0::KiB_64
-            + TCR_EL1::EPD1::DisableTTBR1Walks
+            + TCR_EL1::IRGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+            + TCR_EL1::ORGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+            + TCR_EL1::SH1::Inner
+            + TCR_EL1::TG1::KiB_64
+            + TCR_EL1::EPD1::EnableTTBR1Walks
             + TCR_EL1::EPD0::EnableTTBR0Walks
-            + TCR_EL1::T0SZ.val(t0sz),
+            + TCR_EL1::T0SZ.val(tsz)
+            + TCR_EL1::T1SZ.val(tsz),
     );
 }
 
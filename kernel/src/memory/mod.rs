@@ -1,6 +1,30 @@
+mod frame;
+mod heap;
 mod layout;
 pub mod map;
 pub mod mmu;
+mod probe;
 mod translation_table;
+mod user;
 
-pub use layout::print_kernel_memory_layout;
+#[allow(unused_imports)]
+pub use frame::{FrameAllocator, FrameStats, FRAME_SIZE};
+#[allow(unused_imports)]
+pub use heap::{HeapStats, KernelHeap};
+pub use layout::{print_kernel_memory_layout, AccessPermissions, AttributeFields, MemAttributes};
+#[allow(unused_imports)]
+pub use probe::{probe_read, probe_write, Fault, Probe};
+pub use user::{is_user_range, AddressSpace, USER_SIZE, USER_START};
+
+static FRAME_ALLOCATOR: FrameAllocator = FrameAllocator::new();
+
+pub fn frame_allocator() -> &'static FrameAllocator {
+    &FRAME_ALLOCATOR
+}
+
+#[global_allocator]
+static KERNEL_HEAP: KernelHeap = KernelHeap::new();
+
+pub fn heap() -> &'static KernelHeap {
+    &KERNEL_HEAP
+}
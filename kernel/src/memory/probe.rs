@@ -0,0 +1,104 @@
+use crate::exception::Syndrome;
+use aarch64_cpu::registers::ESR_EL1;
+use core::{arch::asm, fmt::Display};
+use tock_registers::registers::InMemoryRegister;
+
+/// A data abort caught while probing memory
+#[derive(Clone, Copy, Debug)]
+pub struct Fault {
+    pub addr: usize,
+    pub esr: u64,
+}
+
+impl Display for Fault {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let esr = InMemoryRegister::<u64, ESR_EL1::Register>::new(self.esr);
+        write!(f, "{} at {:#018X}", Syndrome::new(&esr), self.addr)
+    }
+}
+
+// The fixup handler puts ESR_EL1 into x16 and FAR_EL1 into x17 and resumes after the faulting
+// instruction, so x16 stays zero if the access went through
+
+/// Types that can be read or written with a single load or store
+pub trait Probe: Sized + Copy {
+    unsafe fn probe_read(addr: usize) -> Result<Self, Fault>;
+    unsafe fn probe_write(addr: usize, val: Self) -> Result<(), Fault>;
+}
+
+macro_rules! impl_probe {
+    ($t:ty, $ldr:literal, $str:literal) => {
+        impl Probe for $t {
+            unsafe fn probe_read(addr: usize) -> Result<Self, Fault> {
+                let val: u64;
+                let esr: u64;
+                let far: u64;
+                asm!(
+                    "mov x16, xzr",
+                    concat!("2: ", $ldr),
+                    "3:",
+                    ".pushsection __ex_table, \"a\"",
+                    ".balign 8",
+                    ".quad 2b, 3b",
+                    ".popsection",
+                    addr = in(reg) addr,
+                    val = out(reg) val,
+                    out("x16") esr,
+                    out("x17") far,
+                    options(nostack),
+                );
+
+                match esr {
+                    0 => Ok(val as $t),
+                    _ => Err(Fault {
+                        addr: far as usize,
+                        esr,
+                    }),
+                }
+            }
+
+            unsafe fn probe_write(addr: usize, val: Self) -> Result<(), Fault> {
+                let esr: u64;
+                let far: u64;
+                asm!(
+                    "mov x16, xzr",
+                    concat!("2: ", $str),
+                    "3:",
+                    ".pushsection __ex_table, \"a\"",
+                    ".balign 8",
+                    ".quad 2b, 3b",
+                    ".popsection",
+                    addr = in(reg) addr,
+                    val = in(reg) val as u64,
+                    out("x16") esr,
+                    out("x17") far,
+                    options(nostack),
+                );
+
+                match esr {
+                    0 => Ok(()),
+                    _ => Err(Fault {
+                        addr: far as usize,
+                        esr,
+                    }),
+                }
+            }
+        }
+    };
+}
+
+impl_probe!(u8, "ldrb {val:w}, [{addr}]", "strb {val:w}, [{addr}]");
+impl_probe!(u16, "ldrh {val:w}, [{addr}]", "strh {val:w}, [{addr}]");
+impl_probe!(u32, "ldr {val:w}, [{addr}]", "str {val:w}, [{addr}]");
+impl_probe!(u64, "ldr {val}, [{addr}]", "str {val}, [{addr}]");
+
+/// Read from `addr`, returning the fault instead of panicking if the access aborts
+pub unsafe fn probe_read<T: Probe>(addr: usize) -> Result<T, Fault> {
+    T::probe_read(addr)
+}
+
+/// Write to `addr`, returning the fault instead of panicking if the access aborts
+#[allow(dead_code)]
+pub unsafe fn probe_write<T: Probe>(addr: usize, val: T) -> Result<(), Fault> {
+    T::probe_write(addr, val)
+}
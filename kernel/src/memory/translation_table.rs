@@ -1,4 +1,4 @@
-use super::layout::*;
+use super::{layout::*, map};
 
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -36,24 +36,43 @@ impl PageDescriptor {
             }
         }
 
-        match attribs.acc_perms {
-            AccessPermissions::ReadOnly => {
+        match (attribs.acc_perms, attribs.el0_accessible) {
+            (AccessPermissions::ReadOnly, false) => {
                 // Read only, only accesible from EL1
                 value |= 0b10 << 6;
             }
-            AccessPermissions::ReadWrite => {
+            (AccessPermissions::ReadWrite, false) => {
                 // Read/Write, only accesible from EL1
                 value |= 0b00 << 6;
             }
+            (AccessPermissions::ReadOnly, true) => {
+                // Read only, accesible from EL1 and EL0
+                value |= 0b11 << 6;
+            }
+            (AccessPermissions::ReadWrite, true) => {
+                // Read/Write, accesible from EL1 and EL0
+                value |= 0b01 << 6;
+            }
         }
 
-        // Default is allow execute so else is not needed
-        if attribs.execute_never {
+        if attribs.el0_accessible {
+            // The kernel never executes user pages
             value |= 1 << 53;
-        }
+            if attribs.execute_never {
+                value |= 1 << 54;
+            }
+
+            // Not global, so the TLB entries are tagged with the ASID
+            value |= 1 << 11;
+        } else {
+            // Default is allow execute so else is not needed
+            if attribs.execute_never {
+                value |= 1 << 53;
+            }
 
-        // Don't allow execution from EL0
-        value |= 1 << 54;
+            // Don't allow execution from EL0
+            value |= 1 << 54;
+        }
 
         let shifted = (addr >> SHIFT_64K) as u64;
         value |= (shifted & 0xFFFF_FFFF) << 16;
@@ -63,6 +82,24 @@ impl PageDescriptor {
 
         Self { value }
     }
+
+    fn is_valid(&self) -> bool {
+        self.value & 0b1 != 0
+    }
+
+    /// AP[2] clear
+    fn is_writable(&self) -> bool {
+        self.value & (1 << 7) == 0
+    }
+
+    /// PXN or UXN clear
+    fn is_executable(&self) -> bool {
+        self.value & (1 << 53) == 0 || self.value & (1 << 54) == 0
+    }
+
+    fn output_addr(&self) -> usize {
+        (((self.value >> 16) & 0xFFFF_FFFF) as usize) << SHIFT_64K
+    }
 }
 
 impl TableDescriptor {
@@ -81,6 +118,53 @@ impl TableDescriptor {
     }
 }
 
+/// Level 2 table that only holds 512 MiB block entries
+#[repr(C)]
+#[repr(align(64))]
+pub(super) struct BlockTable {
+    entries: [u64; KERNEL_LV2_TABLES],
+}
+
+impl BlockTable {
+    const fn empty() -> Self {
+        Self {
+            entries: [0; KERNEL_LV2_TABLES],
+        }
+    }
+
+    /// Flat map of the physical address space, RWX for EL1. Built at compile time so it can be
+    /// used before anything runs in the higher half
+    const fn flat() -> Self {
+        let mut entries = [0; KERNEL_LV2_TABLES];
+
+        let mut i = 0;
+        while i < KERNEL_LV2_TABLES {
+            let addr = (i << SHIFT_512M) as u64;
+            // Valid block, access flag
+            let mut value = 0b01 | 1 << 10 | addr;
+
+            if addr as usize + (1 << SHIFT_512M) > map::mmio::START {
+                // Attribute 0: Device memory, outer sharable, never executable
+                value |= 0b10 << 8 | 1 << 53 | 1 << 54;
+            } else {
+                // Attribute 1: Normal memory, inner sharable
+                value |= 1 << 2 | 0b11 << 8;
+            }
+
+            entries[i] = value;
+            i += 1;
+        }
+
+        Self { entries }
+    }
+
+    /// Address of the table as seen by the running code, the physical one before the kernel
+    /// runs from the higher half
+    pub fn base_addr(&self) -> u64 {
+        &self.entries as *const _ as u64
+    }
+}
+
 #[repr(C)]
 #[repr(align(65536))]
 pub(super) struct TranslationTables<const TABLES: usize> {
@@ -98,30 +182,135 @@ impl<const TABLES: usize> TranslationTables<TABLES> {
 
     pub fn populate(&mut self) -> Result<(), &'static str> {
         for (i, l2_entry) in self.lvl2.iter_mut().enumerate() {
-            let addr = &self.lvl3[i] as *const _ as usize;
+            let addr = map::virt_to_phys(&self.lvl3[i] as *const _ as usize);
             *l2_entry = TableDescriptor::from_addr(addr);
 
             for (j, l3_entry) in self.lvl3[i].iter_mut().enumerate() {
-                let virt_addr = (i << SHIFT_512M) + (j << SHIFT_64K);
-
-                let (output, attribs) = KERNEL_LAYOUT.virt_addr_props(virt_addr)?;
+                let virt_addr = map::phys_to_virt((i << SHIFT_512M) + (j << SHIFT_64K));
 
-                *l3_entry = PageDescriptor::from_addr(output, attribs);
+                *l3_entry = match KERNEL_LAYOUT.virt_addr_props(virt_addr)? {
+                    Some((output, attribs)) => PageDescriptor::from_addr(output, attribs),
+                    None => PageDescriptor::new_zeroed(),
+                };
             }
         }
 
         Ok(())
     }
 
-    pub fn phys_base_addr(&self) -> u64 {
+    fn page_descriptor(&mut self, virt_addr: usize) -> Result<&mut PageDescriptor, &'static str> {
+        let offset = virt_addr
+            .checked_sub(map::VIRT_OFFSET)
+            .ok_or("Address not in the higher half")?;
+        let table = self
+            .lvl3
+            .get_mut(offset >> SHIFT_512M)
+            .ok_or("Address out of range")?;
+        Ok(&mut table[(virt_addr >> SHIFT_64K) & ((1 << 13) - 1)])
+    }
+
+    /// Output address of the 64 KiB page at `virt_addr`, if it is mapped
+    pub fn translate(&mut self, virt_addr: usize) -> Result<Option<usize>, &'static str> {
+        let page = self.page_descriptor(virt_addr)?;
+        Ok(page.is_valid().then(|| page.output_addr()))
+    }
+
+    /// Only writes the descriptor, the caller is responsible for the TLB
+    pub fn map_page(
+        &mut self,
+        virt_addr: usize,
+        output: usize,
+        attribs: AttributeFields,
+    ) -> Result<(), &'static str> {
+        *self.page_descriptor(virt_addr)? = PageDescriptor::from_addr(output, attribs);
+        Ok(())
+    }
+
+    /// Only writes the descriptor, the caller is responsible for the TLB
+    pub fn unmap_page(&mut self, virt_addr: usize) -> Result<(), &'static str> {
+        *self.page_descriptor(virt_addr)? = PageDescriptor::new_zeroed();
+        Ok(())
+    }
+
+    /// First mapped page that is both writable and executable
+    pub fn find_writable_executable(&self) -> Option<usize> {
+        for (i, table) in self.lvl3.iter().enumerate() {
+            for (j, page) in table.iter().enumerate() {
+                if page.is_valid() && page.is_writable() && page.is_executable() {
+                    return Some(map::phys_to_virt((i << SHIFT_512M) + (j << SHIFT_64K)));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Address of the level 2 table as seen by the running code, the physical one before the
+    /// kernel runs from the higher half
+    pub fn base_addr(&self) -> u64 {
         let s = &self.lvl2;
         s as *const _ as u64
     }
+
+    pub fn phys_base_addr(&self) -> u64 {
+        map::virt_to_phys(self.base_addr() as usize) as u64
+    }
+}
+
+/// TTBR0 tables for a user address space. Only the user window is mapped, the kernel lives in
+/// TTBR1
+#[repr(C)]
+#[repr(align(65536))]
+pub(super) struct UserTranslationTables {
+    lvl3: [PageDescriptor; 1 << 13],
+    lvl2: [TableDescriptor; KERNEL_LV2_TABLES],
+}
+
+impl UserTranslationTables {
+    pub const fn new() -> Self {
+        Self {
+            lvl3: [PageDescriptor::new_zeroed(); 1 << 13],
+            lvl2: [TableDescriptor::new_zeroed(); KERNEL_LV2_TABLES],
+        }
+    }
+
+    /// Unmap everything, then point the entry covering the user window at the level 3 table.
+    /// `window_start` has to be aligned to 512 MiB
+    pub fn populate(&mut self, window_start: usize) {
+        let window = window_start >> SHIFT_512M;
+
+        for (i, l2_entry) in self.lvl2.iter_mut().enumerate() {
+            *l2_entry = if i == window {
+                TableDescriptor::from_addr(map::virt_to_phys(&self.lvl3 as *const _ as usize))
+            } else {
+                TableDescriptor::new_zeroed()
+            };
+        }
+
+        self.lvl3.fill(PageDescriptor::new_zeroed());
+    }
+
+    /// Map the 64 KiB page at `virt_addr`, which has to be inside the user window
+    pub fn map_page(&mut self, virt_addr: usize, output: usize, attribs: AttributeFields) {
+        let idx = (virt_addr >> SHIFT_64K) & (self.lvl3.len() - 1);
+        self.lvl3[idx] = PageDescriptor::from_addr(output, attribs);
+    }
+
+    pub fn phys_base_addr(&self) -> u64 {
+        let s = &self.lvl2;
+        map::virt_to_phys(s as *const _ as usize) as u64
+    }
 }
 
 const KERNEL_LV2_TABLES: usize = (super::map::END_INCLUSIVE + 1) >> SHIFT_512M;
 pub(super) static mut KERNEL_TABLES: TranslationTables<KERNEL_LV2_TABLES> =
     TranslationTables::new();
 
+/// Used while a core turns on its MMU. Identity maps the low half so the code doing it keeps
+/// running, and maps the higher half on the boot core until `KERNEL_TABLES` are populated
+pub(super) static BOOT_TABLE: BlockTable = BlockTable::flat();
+/// TTBR0 when no user address space is active
+pub(super) static EMPTY_TABLE: BlockTable = BlockTable::empty();
+
 const SHIFT_64K: usize = (64 as usize * 1024).trailing_zeros() as usize;
 const SHIFT_512M: usize = (512 as usize * 1024 * 1024).trailing_zeros() as usize;
@@ -0,0 +1,139 @@
+use super::{layout::AttributeFields, map, mmu, translation_table::UserTranslationTables};
+use crate::sync::SpinLock;
+use core::arch::asm;
+
+/// Start of the window user pages are mapped in, in the TTBR0 half
+pub const USER_START: usize = 0x4000_0000;
+pub const USER_SIZE: usize = 2 * 1024 * 1024;
+
+const PAGE_SIZE: usize = 64 * 1024;
+const MAX_ADDRESS_SPACES: usize = 2;
+
+#[repr(C)]
+#[repr(align(65536))]
+struct UserMemory([u8; USER_SIZE]);
+
+static mut USER_TABLES: [UserTranslationTables; MAX_ADDRESS_SPACES] =
+    [const { UserTranslationTables::new() }; MAX_ADDRESS_SPACES];
+static mut USER_MEMORY: [UserMemory; MAX_ADDRESS_SPACES] =
+    [const { UserMemory([0; USER_SIZE]) }; MAX_ADDRESS_SPACES];
+static SLOTS_USED: SpinLock<[bool; MAX_ADDRESS_SPACES]> =
+    SpinLock::new([false; MAX_ADDRESS_SPACES]);
+
+/// Whether `[addr, addr + len)` lies inside the user window
+pub fn is_user_range(addr: usize, len: usize) -> bool {
+    match addr.checked_add(len) {
+        Some(end) => addr >= USER_START && end <= USER_START + USER_SIZE,
+        None => false,
+    }
+}
+
+/// Translation tables and backing memory for code running at EL0. Pages in the user window are
+/// backed by a fixed chunk of kernel memory at the same offset, nothing else is mapped in TTBR0
+pub struct AddressSpace {
+    slot: usize,
+}
+
+impl AddressSpace {
+    pub fn new() -> Result<Self, &'static str> {
+        let slot = SLOTS_USED.lock(|used| {
+            let slot = used.iter().position(|u| !u)?;
+            used[slot] = true;
+            Some(slot)
+        });
+        let space = Self {
+            slot: slot.ok_or("No free address space")?,
+        };
+
+        space.tables().populate(USER_START);
+        mmu::invalidate_asid(space.asid());
+
+        Ok(space)
+    }
+
+    /// ASID 0 is used by the kernel tables
+    pub fn asid(&self) -> u16 {
+        self.slot as u16 + 1
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    fn tables(&self) -> &mut UserTranslationTables {
+        let tables = &raw mut USER_TABLES;
+        unsafe { &mut (*tables)[self.slot] }
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    fn memory(&self) -> &mut [u8; USER_SIZE] {
+        let memory = &raw mut USER_MEMORY;
+        unsafe { &mut (*memory)[self.slot].0 }
+    }
+
+    /// Map the pages covering `[virt_addr, virt_addr + len)` with `attribs` and zero them
+    pub fn map(
+        &mut self,
+        virt_addr: usize,
+        len: usize,
+        attribs: AttributeFields,
+    ) -> Result<(), &'static str> {
+        if !is_user_range(virt_addr, len) {
+            return Err("Mapping outside of the user window");
+        }
+
+        let start = virt_addr & !(PAGE_SIZE - 1);
+        let end = (virt_addr + len).next_multiple_of(PAGE_SIZE);
+
+        let memory = self.memory();
+        for page in (start..end).step_by(PAGE_SIZE) {
+            let offset = page - USER_START;
+            memory[offset..offset + PAGE_SIZE].fill(0);
+
+            let output = map::virt_to_phys(&memory[offset] as *const u8 as usize);
+            self.tables().map_page(page, output, attribs);
+        }
+
+        mmu::invalidate_asid(self.asid());
+        Ok(())
+    }
+
+    /// Copy `data` to `virt_addr` through the kernel's mapping of the backing memory, making it
+    /// visible to instruction fetches
+    pub fn write(&mut self, virt_addr: usize, data: &[u8]) -> Result<(), &'static str> {
+        if !is_user_range(virt_addr, data.len()) {
+            return Err("Write outside of the user window");
+        }
+
+        let offset = virt_addr - USER_START;
+        let dst = &mut self.memory()[offset..offset + data.len()];
+        dst.copy_from_slice(data);
+        sync_icache(dst.as_ptr() as usize, dst.len());
+
+        Ok(())
+    }
+
+    /// Make this the current TTBR0 address space
+    pub unsafe fn activate(&self) {
+        mmu::set_ttbr0(self.tables().phys_base_addr(), self.asid());
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        SLOTS_USED.lock(|used| used[self.slot] = false);
+    }
+}
+
+/// Clean the data cache and invalidate the instruction cache for freshly written code
+fn sync_icache(start: usize, len: usize) {
+    let ctr: u64;
+    unsafe { asm!("mrs {}, CTR_EL0", out(reg) ctr, options(nomem, nostack)) };
+    // Log2 of the number of words in the smallest data cache line
+    let line = 4 << ((ctr >> 16) & 0xF);
+
+    let mut addr = start & !(line - 1);
+    while addr < start + len {
+        unsafe { asm!("dc cvau, {}", in(reg) addr, options(nostack)) };
+        addr += line;
+    }
+
+    unsafe { asm!("dsb ish", "ic ialluis", "dsb ish", "isb", options(nostack)) };
+}
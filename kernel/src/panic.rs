@@ -1,13 +1,45 @@
-use crate::{fatal, println};
-use core::panic::PanicInfo;
+use crate::{backtrace::Backtrace, cpu, exception, fatal, log, print, println, smp};
+use core::{
+    fmt::Arguments,
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
+/// Core in `exception_panic` plus one, zero if none
+static EXCEPTION_PANIC_CORE: AtomicUsize = AtomicUsize::new(0);
+
+/// Panic with an exception report. The report has the backtrace of the exception, so the panic
+/// handler skips its own, which would only show the exception handler
+pub fn exception_panic(args: Arguments) -> ! {
+    EXCEPTION_PANIC_CORE.store(cpu::id() + 1, Ordering::Relaxed);
+    panic!("{}", args)
+}
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    exception::irq::mask();
+
+    // Panicking again while reporting, most likely on a lock the first panic left locked
+    if PANICKING.swap(true, Ordering::Relaxed) {
+        cpu::halt()
+    }
+    smp::stop_other_cores();
+
+    // The panic may have hit while this core held the logger or UART lock
+    unsafe { log::logger().force_unlock() };
+    // Interrupts may never drain the transmit buffer again
+    log::logger().set_sync();
+
     fatal!("KERNEL PANIC!:\n{}", info.message());
 
     if let Some(loc) = info.location() {
         println!("in file {}:{}:{}", loc.file(), loc.line(), loc.column())
     }
+    if EXCEPTION_PANIC_CORE.load(Ordering::Relaxed) != cpu::id() + 1 {
+        print!("{}", Backtrace::current());
+    }
+    log::logger().flush();
 
-    loop {}
+    cpu::halt()
 }
@@ -0,0 +1,76 @@
+//! Per-core variables. `per_cpu!` statics are placed in the `.percpu` section, which is only a
+//! template: every core gets its own copy of the section, and TPIDR_EL1 holds the offset from
+//! the template to the copy of the current core
+
+use crate::{cpu, exception::irq};
+use aarch64_cpu::registers::{Readable, Writeable, TPIDR_EL1};
+use core::cell::UnsafeCell;
+
+extern "Rust" {
+    static __percpu_start: UnsafeCell<()>;
+    static __percpu_end: UnsafeCell<()>;
+    static __percpu_areas_start: UnsafeCell<()>;
+}
+
+/// Areas are cache line aligned so cores don't share lines
+const AREA_ALIGN: usize = 64;
+
+/// Set up the area of the current core from the template. Has to run on every core before it
+/// touches a per-core variable
+pub unsafe fn init() {
+    let start = __percpu_start.get() as usize;
+    let size = __percpu_end.get() as usize - start;
+    let area = __percpu_areas_start.get() as usize + cpu::id() * size.next_multiple_of(AREA_ALIGN);
+
+    core::ptr::copy_nonoverlapping(start as *const u8, area as *mut u8, size);
+    TPIDR_EL1.set((area - start) as u64);
+}
+
+/// A variable with a separate instance on every core. Declared with `per_cpu!`
+pub struct PerCpu<T> {
+    /// Set while `with` runs, every core has its own copy like the data
+    borrowed: UnsafeCell<bool>,
+    template: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for PerCpu<T> where T: Send {}
+
+impl<T> PerCpu<T> {
+    #[doc(hidden)]
+    pub const fn new(data: T) -> Self {
+        Self {
+            borrowed: UnsafeCell::new(false),
+            template: UnsafeCell::new(data),
+        }
+    }
+
+    /// Run `f` on the current core's instance. IRQs are masked for the duration, so nothing
+    /// else on this core can touch it and the reference can't outlive a sleep.
+    /// Accessing the same variable again from inside `f` panics
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let daif = irq::save_and_mask();
+        let offset = TPIDR_EL1.get() as usize;
+
+        let borrowed = unsafe { self.borrowed.get().byte_add(offset) };
+        if unsafe { borrowed.replace(true) } {
+            panic!("Per-core variable is already borrowed on this core");
+        }
+
+        let data = unsafe { &mut *self.template.get().byte_add(offset) };
+        let r = f(data);
+
+        unsafe { borrowed.write(false) };
+        irq::restore(daif);
+        r
+    }
+}
+
+/// Declare a per-core static, see `PerCpu`
+#[macro_export]
+macro_rules! per_cpu {
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;) => {
+        $(#[$attr])*
+        #[link_section = ".percpu"]
+        $vis static $name: $crate::percpu::PerCpu<$ty> = $crate::percpu::PerCpu::new($init);
+    };
+}
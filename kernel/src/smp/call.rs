@@ -0,0 +1,192 @@
+use super::NUM_CORES;
+use crate::{
+    cpu,
+    driver::{
+        self,
+        gic::{IRQHandler, IRQHandlerDescriptor, IRQNumber, SGITarget},
+    },
+    sync::IRQSafeLock,
+};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Run the calls queued for the receiving core
+const IPI_CALL: IRQNumber = 0;
+/// Halt the receiving core for good
+const IPI_STOP: IRQNumber = 1;
+
+/// Calls a core can have queued at once
+const QUEUE_SIZE: usize = 8;
+
+type CallFn = dyn Fn() + Sync;
+
+#[derive(Copy, Clone)]
+struct Call {
+    f: *const CallFn,
+    /// Set once `f` returned, if the caller waits for it
+    done: Option<*const AtomicBool>,
+}
+
+/// The pointers are only used while the sender keeps them alive
+unsafe impl Send for Call {}
+
+static QUEUES: [IRQSafeLock<[Option<Call>; QUEUE_SIZE]>; NUM_CORES] =
+    [const { IRQSafeLock::new([None; QUEUE_SIZE]) }; NUM_CORES];
+
+/// Run `f` on `cpu` and wait until it returned there
+#[allow(dead_code)]
+pub fn call_on(cpu: usize, f: &CallFn) -> Result<(), &'static str> {
+    check_online(cpu)?;
+
+    let done = AtomicBool::new(false);
+    unsafe { queue(cpu, f, Some(&done)) };
+
+    while !done.load(Ordering::Acquire) {
+        // Two cores calling each other would deadlock otherwise
+        run_pending();
+        core::hint::spin_loop();
+    }
+    Ok(())
+}
+
+/// Queue `f` to run on `cpu` without waiting for it
+#[allow(dead_code)]
+pub fn call_on_nowait(cpu: usize, f: &'static CallFn) -> Result<(), &'static str> {
+    check_online(cpu)?;
+
+    unsafe { queue(cpu, f, None) };
+    Ok(())
+}
+
+/// Run `f` on every online core, this one included, and wait until all of them are done
+pub fn call_on_all(f: &CallFn) {
+    let done = [const { AtomicBool::new(false) }; NUM_CORES];
+    let this = cpu::id();
+
+    for (core, done) in done.iter().enumerate() {
+        if core != this && super::is_online(core) {
+            unsafe { queue(core, f, Some(done)) };
+        }
+    }
+
+    f();
+
+    for (core, done) in done.iter().enumerate() {
+        if core != this && super::is_online(core) {
+            while !done.load(Ordering::Acquire) {
+                run_pending();
+                core::hint::spin_loop();
+            }
+        }
+    }
+}
+
+/// A core that never came up would never run the call
+fn check_online(cpu: usize) -> Result<(), &'static str> {
+    if cpu < NUM_CORES && super::is_online(cpu) {
+        Ok(())
+    } else {
+        Err("Core is not online")
+    }
+}
+
+/// The caller has to keep `f` and `done` alive until the call ran
+unsafe fn queue(cpu: usize, f: &CallFn, done: Option<&AtomicBool>) {
+    assert!(cpu < NUM_CORES, "Invalid core {}", cpu);
+
+    if cpu == cpu::id() {
+        f();
+        if let Some(done) = done {
+            done.store(true, Ordering::Release);
+        }
+        return;
+    }
+
+    let call = Call {
+        // Erases the lifetime, the caller guarantees `f` outlives the call
+        f: core::mem::transmute::<&CallFn, *const CallFn>(f),
+        done: done.map(|d| d as *const AtomicBool),
+    };
+
+    loop {
+        let queued = QUEUES[cpu].lock(|q| match q.iter_mut().find(|c| c.is_none()) {
+            Some(slot) => {
+                *slot = Some(call);
+                true
+            }
+            None => false,
+        });
+        if queued {
+            break;
+        }
+
+        // Target is busy, let it drain its queue
+        run_pending();
+        core::hint::spin_loop();
+    }
+
+    driver::gic().send_sgi(IPI_CALL, SGITarget::Core(cpu));
+}
+
+/// Run everything queued for this core
+fn run_pending() {
+    let calls = QUEUES[cpu::id()].lock(|q| {
+        let calls = *q;
+        q.fill(None);
+        calls
+    });
+
+    for call in calls.iter().flatten() {
+        unsafe {
+            (*call.f)();
+            if let Some(done) = call.done {
+                (*done).store(true, Ordering::Release);
+            }
+        }
+    }
+}
+
+/// Halt every core except this one. Doesn't wait for them
+pub fn stop_other_cores() {
+    driver::gic().send_sgi(IPI_STOP, SGITarget::AllOthers);
+}
+
+struct IPIHandler {
+    sgi: IRQNumber,
+}
+
+impl IRQHandler for IPIHandler {
+    fn handle(&self) -> Result<(), &'static str> {
+        match self.sgi {
+            IPI_CALL => run_pending(),
+            _ => cpu::halt(),
+        }
+        Ok(())
+    }
+}
+
+static CALL_HANDLER: IPIHandler = IPIHandler { sgi: IPI_CALL };
+static STOP_HANDLER: IPIHandler = IPIHandler { sgi: IPI_STOP };
+
+pub(super) fn init() -> Result<(), &'static str> {
+    let gic = driver::gic();
+    gic.register_handler(IRQHandlerDescriptor {
+        number: IPI_CALL,
+        name: "IPI call",
+        handler: &CALL_HANDLER,
+    })?;
+    gic.register_handler(IRQHandlerDescriptor {
+        number: IPI_STOP,
+        name: "IPI stop",
+        handler: &STOP_HANDLER,
+    })?;
+
+    init_current_core();
+    Ok(())
+}
+
+/// SGI enables are banked, every core has to turn them on for itself
+pub(super) fn init_current_core() {
+    let gic = driver::gic();
+    gic.enable(IPI_CALL);
+    gic.enable(IPI_STOP);
+}
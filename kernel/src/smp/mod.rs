@@ -0,0 +1,110 @@
+use crate::{cpu, driver, info, memory::map, time, warn};
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+mod call;
+
+#[allow(unused_imports)]
+pub use call::{call_on, call_on_all, call_on_nowait, stop_other_cores};
+
+pub const NUM_CORES: usize = 4;
+
+/// How long core 0 waits for the others to report in
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// One bit per core that finished its setup
+static CORES_ONLINE: AtomicUsize = AtomicUsize::new(1);
+
+extern "C" {
+    fn _start_secondary();
+}
+
+/// Release cores 1-3 into `_start_secondary` and wait for them to come online
+pub fn start_secondary_cores() {
+    // The cores start with their MMU off
+    let entry = map::virt_to_phys(_start_secondary as *const () as usize) as u64;
+    for core in 1..NUM_CORES {
+        if let Err(e) = unsafe { release_core(core, entry) } {
+            warn!("Failed to start core {}: {}", core, e);
+        }
+    }
+
+    let start = time::uptime();
+    while num_online() < NUM_CORES {
+        if time::uptime() - start > STARTUP_TIMEOUT {
+            warn!("Only {} of {} cores came online", num_online(), NUM_CORES);
+            return;
+        }
+        core::hint::spin_loop();
+    }
+
+    info!("All {} cores online", NUM_CORES);
+}
+
+/// Set up cross-core calls. Needs the GIC
+pub fn init() -> Result<(), &'static str> {
+    call::init()
+}
+
+/// Per-core interrupt setup for a secondary core
+pub fn init_current_core() {
+    driver::gic().init_current_core();
+    call::init_current_core();
+    time::timer_queue().init_current_core();
+}
+
+/// Called by every secondary core once it is fully set up
+pub fn mark_online() {
+    CORES_ONLINE.fetch_or(1 << cpu::id(), Ordering::Release);
+}
+
+pub fn is_online(core: usize) -> bool {
+    CORES_ONLINE.load(Ordering::Acquire) & (1 << core) != 0
+}
+
+fn num_online() -> usize {
+    CORES_ONLINE.load(Ordering::Acquire).count_ones() as usize
+}
+
+/// The firmware's armstub parks cores 1-3 polling these addresses, 0xD8 belongs to core 0
+#[cfg(not(feature = "psci"))]
+const SPIN_TABLE: [usize; NUM_CORES] = [0xD8, 0xE0, 0xE8, 0xF0];
+
+#[cfg(not(feature = "psci"))]
+unsafe fn release_core(core: usize, entry: u64) -> Result<(), &'static str> {
+    use aarch64_cpu::asm::{self, barrier};
+
+    let addr = map::phys_to_virt(SPIN_TABLE[core]) as *mut u64;
+    addr.write_volatile(entry);
+
+    // The parked core polls with its caches off, so the write has to reach memory
+    core::arch::asm!("dc civac, {}", in(reg) addr, options(nostack));
+    barrier::dsb(barrier::SY);
+    asm::sev();
+
+    Ok(())
+}
+
+#[cfg(feature = "psci")]
+unsafe fn release_core(core: usize, entry: u64) -> Result<(), &'static str> {
+    const PSCI_CPU_ON: u64 = 0xC400_0003;
+
+    let ret: i64;
+    core::arch::asm!(
+        "smc #0",
+        inout("x0") PSCI_CPU_ON => ret,
+        in("x1") core,
+        in("x2") entry,
+        in("x3") 0,
+        options(nostack),
+    );
+
+    match ret {
+        0 => Ok(()),
+        -2 => Err("PSCI: invalid parameters"),
+        -4 => Err("PSCI: core already on"),
+        _ => Err("PSCI: CPU_ON failed"),
+    }
+}
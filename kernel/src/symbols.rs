@@ -0,0 +1,97 @@
+use core::{cell::UnsafeCell, fmt::Display};
+
+/// Space reserved for the symbol table. `tools/ksyms` fills it in after linking and fails the
+/// build if the table does not fit
+const KSYMS_SIZE: usize = 256 * 1024;
+
+const MAGIC: &[u8; 4] = b"KSYM";
+const HEADER_SIZE: usize = 8;
+const ENTRY_SIZE: usize = 24;
+
+#[used]
+#[link_section = ".ksyms"]
+static KSYMS: [u8; KSYMS_SIZE] = [0; KSYMS_SIZE];
+
+extern "Rust" {
+    static __ksyms_start: UnsafeCell<u8>;
+    static __ksyms_end: UnsafeCell<u8>;
+}
+
+/// Goes through the linker symbols so the compiler can't assume the table is still all zeroes
+fn table() -> &'static [u8] {
+    unsafe {
+        let start = __ksyms_start.get() as *const u8;
+        let end = __ksyms_end.get() as *const u8;
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+struct Entry {
+    addr: usize,
+    size: usize,
+    name: &'static str,
+}
+
+fn u32_at(b: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(b[off..off + 4].try_into().unwrap())
+}
+
+fn u64_at(b: &[u8], off: usize) -> u64 {
+    u64::from_le_bytes(b[off..off + 8].try_into().unwrap())
+}
+
+fn entry(t: &'static [u8], idx: usize) -> Option<Entry> {
+    let off = HEADER_SIZE + idx * ENTRY_SIZE;
+    let name_off = u32_at(t, off + 12) as usize;
+    let name_len = u32_at(t, off + 16) as usize;
+
+    Some(Entry {
+        addr: u64_at(t, off) as usize,
+        size: u32_at(t, off + 8) as usize,
+        name: core::str::from_utf8(t.get(name_off..name_off + name_len)?).ok()?,
+    })
+}
+
+/// Name of the function containing `addr` and the offset into it
+pub fn lookup(addr: usize) -> Option<(&'static str, usize)> {
+    let t = table();
+    if t.len() < HEADER_SIZE || &t[0..4] != MAGIC {
+        return None;
+    }
+
+    let count = u32_at(t, 4) as usize;
+    if HEADER_SIZE + count * ENTRY_SIZE > t.len() {
+        return None;
+    }
+
+    // Last symbol starting at or below addr
+    let (mut lo, mut hi) = (0, count);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if u64_at(t, HEADER_SIZE + mid * ENTRY_SIZE) as usize <= addr {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    let e = entry(t, lo.checked_sub(1)?)?;
+
+    let offset = addr - e.addr;
+    if e.size != 0 && offset >= e.size {
+        return None;
+    }
+    Some((e.name, offset))
+}
+
+/// Formats an address as `0x... (function+0x...)` when the symbol is known
+pub struct Symbolized(pub usize);
+
+impl Display for Symbolized {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:#018X}", self.0)?;
+        if let Some((name, offset)) = lookup(self.0) {
+            write!(f, " ({}+{:#X})", name, offset)?;
+        }
+        Ok(())
+    }
+}
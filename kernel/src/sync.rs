@@ -1,24 +1,136 @@
-use core::cell::UnsafeCell;
+use crate::exception::irq;
+use aarch64_cpu::asm;
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicU32, Ordering},
+};
 
-pub struct NullLock<T>
+#[cfg(debug_assertions)]
+use crate::cpu;
+#[cfg(debug_assertions)]
+use core::{sync::atomic::AtomicUsize, time::Duration};
+
+/// Ticket lock. Cores are served in the order they asked for the lock and sleep in `wfe` while
+/// waiting. Exclusive accesses only work on cacheable memory, so this can't be used before the
+/// MMU is on
+pub struct SpinLock<T>
 where
     T: ?Sized,
 {
+    next_ticket: AtomicU32,
+    now_serving: AtomicU32,
+
+    /// Core holding the lock plus one, zero if it is free
+    #[cfg(debug_assertions)]
+    owner: AtomicUsize,
+
     data: UnsafeCell<T>,
 }
 
-unsafe impl<T> Send for NullLock<T> where T: ?Sized + Send {}
-unsafe impl<T> Sync for NullLock<T> where T: ?Sized + Send {}
+unsafe impl<T> Send for SpinLock<T> where T: ?Sized + Send {}
+unsafe impl<T> Sync for SpinLock<T> where T: ?Sized + Send {}
 
-impl<T> NullLock<T> {
+/// How long a core waits for a lock before debug builds report a deadlock
+#[cfg(debug_assertions)]
+const DEADLOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+impl<T> SpinLock<T> {
     pub const fn new(data: T) -> Self {
         Self {
+            next_ticket: AtomicU32::new(0),
+            now_serving: AtomicU32::new(0),
+            #[cfg(debug_assertions)]
+            owner: AtomicUsize::new(0),
             data: UnsafeCell::new(data),
         }
     }
 
     pub fn lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+
+        #[cfg(debug_assertions)]
+        let start = crate::time::uptime();
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            #[cfg(debug_assertions)]
+            self.check_deadlock(start);
+
+            // Woken by the `sev` in the release below
+            #[cfg(not(debug_assertions))]
+            asm::wfe();
+        }
+
+        #[cfg(debug_assertions)]
+        self.owner.store(cpu::id() + 1, Ordering::Relaxed);
+
         let data = unsafe { &mut *self.data.get() };
-        f(data)
+        let r = f(data);
+
+        #[cfg(debug_assertions)]
+        self.owner.store(0, Ordering::Relaxed);
+
+        self.now_serving
+            .store(ticket.wrapping_add(1), Ordering::Release);
+        asm::sev();
+
+        r
+    }
+
+    /// Release the lock whoever holds it. Only for the panic handler, where the holder is either
+    /// the interrupted code on this core or a core that was told to stop, and neither comes back
+    pub unsafe fn force_unlock(&self) {
+        #[cfg(debug_assertions)]
+        self.owner.store(0, Ordering::Relaxed);
+
+        self.now_serving
+            .store(self.next_ticket.load(Ordering::Relaxed), Ordering::Release);
+        asm::sev();
+    }
+
+    /// Debug builds spin instead of sleeping, so a lock that is never released gets noticed
+    #[cfg(debug_assertions)]
+    fn check_deadlock(&self, start: Duration) {
+        let owner = self.owner.load(Ordering::Relaxed);
+        if owner == cpu::id() + 1 {
+            panic!(
+                "Deadlock: lock is already held by this core ({})",
+                owner - 1
+            );
+        }
+        if crate::time::uptime() - start > DEADLOCK_TIMEOUT {
+            match owner {
+                0 => panic!("Deadlock: timed out waiting for a lock"),
+                _ => panic!("Deadlock: lock held by core {} for too long", owner - 1),
+            }
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Spin lock that also masks IRQs on the current core while locked, so interrupt handlers can't
+/// run in the middle of a critical section and deadlock on the same lock
+pub struct IRQSafeLock<T>
+where
+    T: ?Sized,
+{
+    inner: SpinLock<T>,
+}
+
+impl<T> IRQSafeLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            inner: SpinLock::new(data),
+        }
+    }
+
+    pub fn lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let daif = irq::save_and_mask();
+        let r = self.inner.lock(f);
+        irq::restore(daif);
+        r
+    }
+
+    /// See `SpinLock::force_unlock`
+    pub unsafe fn force_unlock(&self) {
+        self.inner.force_unlock();
     }
 }
@@ -0,0 +1,43 @@
+use super::{SyscallDescriptor, SyscallError, SyscallResult, MAX_ARGS};
+use crate::{memory, print};
+
+pub mod nr {
+    use super::super::SyscallNumber;
+
+    /// write(ptr, len): print a string to the console
+    pub const WRITE: SyscallNumber = 0;
+    /// exit(code): end the calling user task
+    pub const EXIT: SyscallNumber = 1;
+}
+
+/// Longest string accepted by `WRITE`
+const MAX_WRITE_LEN: usize = 4096;
+
+fn write(args: &[u64; MAX_ARGS], from_user: bool) -> SyscallResult {
+    let (ptr, len) = (args[0] as usize, args[1] as usize);
+    if len > MAX_WRITE_LEN {
+        return Err(SyscallError::InvalidArgument);
+    }
+    // EL0 only gets to print its own memory
+    if from_user && !memory::is_user_range(ptr, len) {
+        return Err(SyscallError::BadAddress);
+    }
+
+    let mut buf = [0u8; MAX_WRITE_LEN];
+    for (i, b) in buf[..len].iter_mut().enumerate() {
+        // The caller's pointer can't be trusted, probing turns bad addresses into an error
+        *b = unsafe { memory::probe_read::<u8>(ptr + i) }.map_err(|_| SyscallError::BadAddress)?;
+    }
+
+    let s = core::str::from_utf8(&buf[..len]).map_err(|_| SyscallError::InvalidArgument)?;
+    print!("{}", s);
+    Ok(len as u64)
+}
+
+pub(super) fn register() -> Result<(), &'static str> {
+    super::register(SyscallDescriptor {
+        number: nr::WRITE,
+        name: "write",
+        handler: write,
+    })
+}
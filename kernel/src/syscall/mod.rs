@@ -0,0 +1,152 @@
+use crate::{
+    exception::{self, ExceptionContext, ExceptionKind, ExceptionResult, VectorGroup},
+    info,
+    sync::SpinLock,
+};
+use aarch64_cpu::registers::ESR_EL1;
+use core::arch::asm;
+
+mod builtin;
+
+pub use builtin::nr;
+
+pub type SyscallNumber = usize;
+
+pub const MAX_SYSCALLS: usize = 64;
+/// Arguments are passed in x0-x5, the syscall number in x8
+pub const MAX_ARGS: usize = 6;
+const NR_REG: usize = 8;
+/// Negative return values down to this are error codes
+const MAX_ERRNO: i64 = 4095;
+
+/// Errors are returned to the caller as the negated value in x0
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(i64)]
+pub enum SyscallError {
+    /// No handler is registered for the syscall number
+    NoSys = 1,
+    InvalidArgument = 2,
+    /// A pointer argument could not be read
+    BadAddress = 3,
+}
+
+impl SyscallError {
+    fn from_code(code: i64) -> Self {
+        match code {
+            2 => Self::InvalidArgument,
+            3 => Self::BadAddress,
+            _ => Self::NoSys,
+        }
+    }
+}
+
+pub type SyscallResult = Result<u64, SyscallError>;
+/// Gets the arguments and whether the caller was running at EL0
+pub type SyscallHandler = fn(args: &[u64; MAX_ARGS], from_user: bool) -> SyscallResult;
+
+#[derive(Copy, Clone)]
+pub struct SyscallDescriptor {
+    pub number: SyscallNumber,
+    pub name: &'static str,
+    pub handler: SyscallHandler,
+}
+
+static SYSCALLS: SpinLock<[Option<SyscallDescriptor>; MAX_SYSCALLS]> =
+    SpinLock::new([None; MAX_SYSCALLS]);
+
+pub fn register(descriptor: SyscallDescriptor) -> Result<(), &'static str> {
+    SYSCALLS.lock(|t| {
+        let slot = t
+            .get_mut(descriptor.number)
+            .ok_or("Syscall number out of range")?;
+        if slot.is_some() {
+            return Err("Syscall already registered");
+        }
+
+        *slot = Some(descriptor);
+        info!(
+            "Registered syscall {} as number {}",
+            descriptor.name, descriptor.number
+        );
+        Ok(())
+    })
+}
+
+fn handle_kernel_svc(ctx: &mut ExceptionContext) -> ExceptionResult {
+    handle_svc(ctx, false)
+}
+
+fn handle_user_svc(ctx: &mut ExceptionContext) -> ExceptionResult {
+    handle_svc(ctx, true)
+}
+
+fn handle_svc(ctx: &mut ExceptionContext, from_user: bool) -> ExceptionResult {
+    let nr = ctx.regs[NR_REG] as SyscallNumber;
+    let mut args = [0; MAX_ARGS];
+    args.copy_from_slice(&ctx.regs[..MAX_ARGS]);
+
+    // Handlers run without the table lock held, so they can register other syscalls
+    let result = match SYSCALLS.lock(|t| t.get(nr).copied().flatten()) {
+        Some(d) => (d.handler)(&args, from_user),
+        None => Err(SyscallError::NoSys),
+    };
+
+    // ELR_EL1 already points past the svc instruction
+    ctx.regs[0] = match result {
+        Ok(v) => v,
+        Err(e) => (-(e as i64)) as u64,
+    };
+    ExceptionResult::Handled
+}
+
+pub fn init() -> Result<(), &'static str> {
+    exception::register_handler(
+        VectorGroup::CurrentSPx,
+        ExceptionKind::Sync(ESR_EL1::EC::Value::SVC64),
+        handle_kernel_svc,
+    )?;
+    exception::register_handler(
+        VectorGroup::LowerAArch64,
+        ExceptionKind::Sync(ESR_EL1::EC::Value::SVC64),
+        handle_user_svc,
+    )?;
+
+    builtin::register()
+}
+
+/// Issue a system call. Use the `syscall!` macro instead of calling this directly
+pub fn invoke(nr: SyscallNumber, args: [u64; MAX_ARGS]) -> SyscallResult {
+    let ret: u64;
+    unsafe {
+        asm!(
+            "svc #0",
+            inout("x0") args[0] => ret,
+            in("x1") args[1],
+            in("x2") args[2],
+            in("x3") args[3],
+            in("x4") args[4],
+            in("x5") args[5],
+            in("x8") nr,
+            options(nostack),
+        );
+    }
+
+    // Error codes are small, anything larger is a valid result
+    let code = ret as i64;
+    if (-MAX_ERRNO..0).contains(&code) {
+        Err(SyscallError::from_code(-code))
+    } else {
+        Ok(ret)
+    }
+}
+
+/// Make a system call with up to `MAX_ARGS` arguments, each converted with `as u64`
+#[macro_export]
+macro_rules! syscall {
+    ($nr:expr $(, $arg:expr)* $(,)?) => {{
+        let mut args = [0u64; $crate::syscall::MAX_ARGS];
+        let vals: &[u64] = &[$($arg as u64),*];
+        args[..vals.len()].copy_from_slice(vals);
+        $crate::syscall::invoke($nr, args)
+    }};
+}
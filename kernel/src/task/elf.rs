@@ -0,0 +1,205 @@
+use super::UserTask;
+use crate::memory::{
+    AccessPermissions, AddressSpace, AttributeFields, MemAttributes, USER_SIZE, USER_START,
+};
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_AARCH64: u16 = 183;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+const PAGE_SIZE: usize = 64 * 1024;
+const STACK_SIZE: usize = 64 * 1024;
+/// Most loadable segments an image can have
+const MAX_SEGMENTS: usize = 8;
+
+fn u16_at(b: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes(b[off..off + 2].try_into().unwrap())
+}
+
+fn u32_at(b: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(b[off..off + 4].try_into().unwrap())
+}
+
+fn u64_at(b: &[u8], off: usize) -> usize {
+    u64::from_le_bytes(b[off..off + 8].try_into().unwrap()) as usize
+}
+
+#[derive(Copy, Clone)]
+struct Segment {
+    offset: usize,
+    vaddr: usize,
+    file_size: usize,
+    mem_size: usize,
+    flags: u32,
+}
+
+impl Segment {
+    /// Page aligned range covered by the segment
+    fn pages(&self) -> (usize, usize) {
+        (
+            self.vaddr & !(PAGE_SIZE - 1),
+            (self.vaddr + self.mem_size).next_multiple_of(PAGE_SIZE),
+        )
+    }
+
+    fn attribute_fields(&self) -> AttributeFields {
+        AttributeFields {
+            mem_attributes: MemAttributes::CacheableDRAM,
+            acc_perms: if self.flags & PF_W != 0 {
+                AccessPermissions::ReadWrite
+            } else {
+                AccessPermissions::ReadOnly
+            },
+            execute_never: self.flags & PF_X == 0,
+            el0_accessible: true,
+        }
+    }
+}
+
+/// A validated ELF64 AArch64 executable
+struct Elf<'a> {
+    image: &'a [u8],
+    entry: usize,
+    segments: [Option<Segment>; MAX_SEGMENTS],
+}
+
+impl<'a> Elf<'a> {
+    fn parse(image: &'a [u8]) -> Result<Self, &'static str> {
+        if image.len() < EHDR_SIZE || &image[0..4] != b"\x7FELF" {
+            return Err("Not an ELF file");
+        }
+        if image[4] != ELFCLASS64 || image[5] != ELFDATA2LSB || image[6] != EV_CURRENT {
+            return Err("Not a little endian ELF64 file");
+        }
+        if u16_at(image, 0x10) != ET_EXEC {
+            return Err("Not an executable");
+        }
+        if u16_at(image, 0x12) != EM_AARCH64 {
+            return Err("Not an AArch64 executable");
+        }
+
+        let entry = u64_at(image, 0x18);
+        let phoff = u64_at(image, 0x20);
+        let phentsize = u16_at(image, 0x36) as usize;
+        let phnum = u16_at(image, 0x38) as usize;
+
+        if phentsize != PHDR_SIZE {
+            return Err("Unexpected program header size");
+        }
+        match phnum
+            .checked_mul(PHDR_SIZE)
+            .and_then(|s| s.checked_add(phoff))
+        {
+            Some(end) if end <= image.len() => {}
+            _ => return Err("Program headers out of bounds"),
+        }
+
+        let mut elf = Self {
+            image,
+            entry,
+            segments: [None; MAX_SEGMENTS],
+        };
+        let mut count = 0;
+
+        for off in (0..phnum).map(|i| phoff + i * PHDR_SIZE) {
+            if u32_at(image, off) != PT_LOAD {
+                continue;
+            }
+
+            let seg = Segment {
+                offset: u64_at(image, off + 0x08),
+                vaddr: u64_at(image, off + 0x10),
+                file_size: u64_at(image, off + 0x20),
+                mem_size: u64_at(image, off + 0x28),
+                flags: u32_at(image, off + 0x04),
+            };
+            elf.validate(&seg)?;
+
+            *elf.segments
+                .get_mut(count)
+                .ok_or("Too many loadable segments")? = Some(seg);
+            count += 1;
+        }
+
+        if count == 0 {
+            return Err("No loadable segments");
+        }
+        if !elf
+            .segments()
+            .any(|s| s.flags & PF_X != 0 && s.vaddr <= entry && entry < s.vaddr + s.mem_size)
+        {
+            return Err("Entry point is not in an executable segment");
+        }
+
+        Ok(elf)
+    }
+
+    fn validate(&self, seg: &Segment) -> Result<(), &'static str> {
+        if seg.file_size > seg.mem_size {
+            return Err("Segment file size larger than memory size");
+        }
+        match seg.offset.checked_add(seg.file_size) {
+            Some(end) if end <= self.image.len() => {}
+            _ => return Err("Segment data out of bounds"),
+        }
+
+        let (start, end) = match seg.vaddr.checked_add(seg.mem_size) {
+            Some(end) if seg.vaddr >= USER_START && end <= USER_START + USER_SIZE - STACK_SIZE => {
+                seg.pages()
+            }
+            _ => return Err("Segment outside of the user window"),
+        };
+
+        if seg.flags & PF_W != 0 && seg.flags & PF_X != 0 {
+            return Err("Segment is both writable and executable");
+        }
+
+        // Pages are mapped with the permissions of a single segment
+        if self.segments().any(|s| {
+            let (s_start, s_end) = s.pages();
+            start < s_end && s_start < end
+        }) {
+            return Err("Segments share a page");
+        }
+
+        Ok(())
+    }
+
+    fn segments(&self) -> impl Iterator<Item = &Segment> {
+        self.segments.iter().flatten()
+    }
+}
+
+/// Load an executable into a new address space, with a stack at the top of the user window
+pub fn load(image: &[u8]) -> Result<UserTask, &'static str> {
+    let elf = Elf::parse(image)?;
+    let mut space = AddressSpace::new()?;
+
+    for seg in elf.segments() {
+        space.map(seg.vaddr, seg.mem_size, seg.attribute_fields())?;
+        space.write(seg.vaddr, &image[seg.offset..seg.offset + seg.file_size])?;
+    }
+
+    let stack_top = USER_START + USER_SIZE;
+    space.map(
+        stack_top - STACK_SIZE,
+        STACK_SIZE,
+        AttributeFields {
+            mem_attributes: MemAttributes::CacheableDRAM,
+            acc_perms: AccessPermissions::ReadWrite,
+            execute_never: true,
+            el0_accessible: true,
+        },
+    )?;
+
+    Ok(UserTask::new(space, elf.entry, stack_top))
+}
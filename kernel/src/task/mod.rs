@@ -0,0 +1,176 @@
+use crate::{
+    exception::{self, ExceptionContext, ExceptionKind, ExceptionResult, Syndrome, VectorGroup},
+    memory::{self, AddressSpace},
+    per_cpu,
+    syscall::{self, nr, SyscallDescriptor, SyscallError, SyscallResult, MAX_ARGS},
+};
+use aarch64_cpu::registers::ESR_EL1;
+use core::{arch::global_asm, fmt::Display};
+use tock_registers::{interfaces::Readable, registers::InMemoryRegister};
+
+mod elf;
+
+pub use elf::load;
+
+global_asm!(include_str!("entry.S"));
+
+/// Callee saved kernel state from before entering a task, layout is shared with `entry.S`
+#[repr(C)]
+#[derive(Default)]
+struct KernelContext {
+    x19_to_lr: [u64; 12],
+    sp: u64,
+    daif: u64,
+    d8_to_d15: [u64; 8],
+}
+
+extern "C" {
+    fn __user_enter(entry: u64, sp: u64, ctx: *mut KernelContext);
+    fn __user_exit(ctx: *const KernelContext) -> !;
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum TaskExit {
+    /// The task called the exit syscall
+    Exited(u64),
+    /// The task caused an exception the kernel doesn't handle for it
+    Faulted { esr: u64, elr: u64, far: u64 },
+}
+
+impl Display for TaskExit {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match *self {
+            Self::Exited(code) => write!(f, "exited with code {}", code),
+            Self::Faulted { esr, elr, far } => {
+                let syndrome = Syndrome::new(&InMemoryRegister::new(esr));
+                write!(f, "killed by {} at {:#X} (FAR {:#X})", syndrome, elr, far)
+            }
+        }
+    }
+}
+
+struct Running {
+    kernel: KernelContext,
+    exit: Option<TaskExit>,
+}
+
+per_cpu! {
+    /// The task currently executing at EL0 on this core, if any
+    static RUNNING: Option<Running> = None;
+}
+
+/// Code running at EL0 in its own address space
+pub struct UserTask {
+    space: AddressSpace,
+    entry: usize,
+    stack_top: usize,
+}
+
+impl UserTask {
+    pub fn new(space: AddressSpace, entry: usize, stack_top: usize) -> Self {
+        Self {
+            space,
+            entry,
+            stack_top,
+        }
+    }
+
+    /// Run the task until it exits or faults
+    pub fn run(&mut self) -> Result<TaskExit, &'static str> {
+        let ctx = RUNNING.with(|r| {
+            if r.is_some() {
+                return Err("A task is already running");
+            }
+            let r = r.insert(Running {
+                kernel: KernelContext::default(),
+                exit: None,
+            });
+            Ok(&mut r.kernel as *mut KernelContext)
+        })?;
+
+        unsafe {
+            self.space.activate();
+            __user_enter(self.entry as u64, self.stack_top as u64, ctx);
+            memory::mmu::activate_kernel_tables();
+        }
+
+        RUNNING
+            .with(|r| r.take())
+            .and_then(|r| r.exit)
+            .ok_or("Task returned without an exit reason")
+    }
+}
+
+/// Stop the running task and return to its `UserTask::run` call.
+/// Does nothing if there is no task
+fn exit_current(reason: TaskExit) {
+    let ctx = RUNNING.with(|r| {
+        let r = r.as_mut()?;
+        r.exit = Some(reason);
+        Some(&r.kernel as *const KernelContext)
+    });
+
+    if let Some(ctx) = ctx {
+        unsafe { __user_exit(ctx) }
+    }
+}
+
+fn exit(args: &[u64; MAX_ARGS], from_user: bool) -> SyscallResult {
+    if from_user {
+        exit_current(TaskExit::Exited(args[0]));
+    }
+    // Only reachable when not called from a task
+    Err(SyscallError::InvalidArgument)
+}
+
+fn handle_user_fault(ctx: &mut ExceptionContext) -> ExceptionResult {
+    exit_current(TaskExit::Faulted {
+        esr: ctx.esr_el1.get(),
+        elr: ctx.elr_el1,
+        far: ctx.far_el1,
+    });
+    ExceptionResult::Unhandled
+}
+
+pub fn init() -> Result<(), &'static str> {
+    use ESR_EL1::EC::Value::*;
+
+    // Everything a task can cause on its own. The rest still ends up in the default handler
+    for ec in [
+        Unknown,
+        TrappedWFIorWFE,
+        TrappedFP,
+        IllegalExecutionState,
+        TrappedMsrMrs,
+        InstrAbortLowerEL,
+        PCAlignmentFault,
+        DataAbortLowerEL,
+        SPAlignmentFault,
+        BreakpointLowerEL,
+        SoftwareStepLowerEL,
+        WatchpointLowerEL,
+        TrappedFP64,
+        Brk64,
+    ] {
+        exception::register_handler(
+            VectorGroup::LowerAArch64,
+            ExceptionKind::Sync(ec),
+            handle_user_fault,
+        )?;
+    }
+
+    syscall::register(SyscallDescriptor {
+        number: nr::EXIT,
+        name: "exit",
+        handler: exit,
+    })
+}
+
+/// The program from `user/hello`, if it was built before the kernel
+pub fn user_image() -> Option<&'static [u8]> {
+    #[cfg(user_image)]
+    return Some(include_bytes!(env!("USER_IMAGE")));
+
+    #[cfg(not(user_image))]
+    None
+}
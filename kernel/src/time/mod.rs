@@ -1,6 +1,19 @@
-use crate::warn;
+use crate::{
+    cpu,
+    exception::{self, irq},
+    warn,
+};
 use aarch64_cpu::registers::{Readable, CNTFRQ_EL0, CNTPCT_EL0};
 use core::{arch::asm, ops::Add, time::Duration};
+use queue::TimerQueue;
+
+pub mod queue;
+
+static TIMER_QUEUE: TimerQueue = TimerQueue::new();
+
+pub fn timer_queue() -> &'static TimerQueue {
+    &TIMER_QUEUE
+}
 
 #[derive(Clone, Copy, PartialEq, PartialOrd)]
 struct TimerValue(u64);
@@ -63,6 +76,7 @@ pub fn uptime() -> Duration {
     current_cntpct().into()
 }
 
+#[allow(dead_code)]
 pub fn spin_for(duration: Duration) {
     let curr_count = current_cntpct();
 
@@ -76,3 +90,48 @@ pub fn spin_for(duration: Duration) {
     let target = curr_count + count_delta;
     while TimerValue(CNTPCT_EL0.get()) < target {}
 }
+
+/// Park the core until `duration` has passed
+pub fn sleep_for(duration: Duration) {
+    sleep_until(uptime() + duration)
+}
+
+/// Park the core until the uptime reaches `deadline`.
+/// Falls back to spinning if the timer interrupt can't be used to wake the core up, like inside
+/// an IRQ handler
+pub fn sleep_until(deadline: Duration) {
+    let target: TimerValue = match deadline.try_into() {
+        Ok(x) => x,
+        Err(s) => {
+            warn!("ignoring sleep until {:?}. {}", deadline, s);
+            return;
+        }
+    };
+    if current_cntpct() >= target {
+        return;
+    }
+
+    if exception::irq::in_interrupt() {
+        while current_cntpct() < target {}
+        return;
+    }
+
+    let handle = match TIMER_QUEUE.wake_at(target) {
+        Ok(h) => h,
+        Err(_) => {
+            while current_cntpct() < target {}
+            return;
+        }
+    };
+
+    // Checked with IRQs masked, so the timer can't fire between the check and the wfi. It still
+    // wakes the core up while pending and runs once IRQs are restored
+    let daif = irq::save_and_mask();
+    while current_cntpct() < target {
+        cpu::wait_for_interrupt();
+        irq::restore(daif);
+        irq::mask();
+    }
+    irq::restore(daif);
+    TIMER_QUEUE.cancel(handle);
+}
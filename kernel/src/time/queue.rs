@@ -0,0 +1,261 @@
+use super::{current_cntpct, TimerValue};
+use crate::{
+    cpu,
+    driver::{
+        gic::{IRQHandler, IRQHandlerDescriptor, IRQNumber},
+        Driver,
+    },
+    smp::NUM_CORES,
+    sync::IRQSafeLock,
+};
+use aarch64_cpu::registers::{Writeable, CNTP_CTL_EL0, CNTP_CVAL_EL0};
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+const MAX_TIMERS: usize = 32;
+
+/// Identifies a scheduled timer so it can be cancelled later
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TimerHandle {
+    core: usize,
+    slot: usize,
+    id: u64,
+}
+
+#[derive(Clone, Copy)]
+struct Timer {
+    id: u64,
+    deadline: TimerValue,
+    period: Option<TimerValue>,
+    callback: fn(),
+}
+
+struct TimerQueueInner {
+    timers: [Option<Timer>; MAX_TIMERS],
+    next_id: u64,
+
+    /// Set once the timer interrupt is enabled on this core and can be relied on
+    ready: bool,
+}
+
+impl TimerQueueInner {
+    const fn new() -> Self {
+        Self {
+            timers: [None; MAX_TIMERS],
+            next_id: 0,
+
+            ready: false,
+        }
+    }
+
+    /// Only called on the core owning the queue, the compare register is per core
+    fn add(
+        &mut self,
+        core: usize,
+        deadline: TimerValue,
+        period: Option<TimerValue>,
+        callback: fn(),
+    ) -> Result<TimerHandle, &'static str> {
+        let (slot, entry) = self
+            .timers
+            .iter_mut()
+            .enumerate()
+            .find(|(_, t)| t.is_none())
+            .ok_or("Too many timers scheduled")?;
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        *entry = Some(Timer {
+            id,
+            deadline,
+            period,
+            callback,
+        });
+        self.rearm();
+
+        Ok(TimerHandle { core, slot, id })
+    }
+
+    /// From another core the compare register is left alone, the owner rearms when the stale
+    /// deadline fires and finds nothing to run
+    fn cancel(&mut self, handle: TimerHandle) -> bool {
+        let entry = &mut self.timers[handle.slot];
+        if !entry.is_some_and(|t| t.id == handle.id) {
+            return false;
+        }
+
+        *entry = None;
+        if handle.core == cpu::id() {
+            self.rearm();
+        }
+        true
+    }
+
+    /// Remove every timer that has expired, reschedule the periodic ones and return the
+    /// callbacks that need to run
+    fn expire(&mut self, now: TimerValue) -> [Option<fn()>; MAX_TIMERS] {
+        let mut expired = [None; MAX_TIMERS];
+
+        for (entry, cb) in self.timers.iter_mut().zip(expired.iter_mut()) {
+            let Some(t) = entry else {
+                continue;
+            };
+            if t.deadline > now {
+                continue;
+            }
+
+            *cb = Some(t.callback);
+            match t.period {
+                // Skip the periods that were missed instead of firing them in a burst
+                Some(period) => {
+                    t.deadline = t.deadline + period;
+                    if t.deadline <= now {
+                        t.deadline = now + period;
+                    }
+                }
+                None => *entry = None,
+            }
+        }
+
+        self.rearm();
+        expired
+    }
+
+    /// Program the compare register with the earliest deadline, or mask the timer when
+    /// nothing is scheduled
+    fn rearm(&self) {
+        let next = self
+            .timers
+            .iter()
+            .flatten()
+            .map(|t| t.deadline)
+            .reduce(|a, b| if b < a { b } else { a });
+
+        match next {
+            None => CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::SET + CNTP_CTL_EL0::IMASK::SET),
+            Some(deadline) => {
+                CNTP_CVAL_EL0.set(deadline.0);
+                CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::SET + CNTP_CTL_EL0::IMASK::CLEAR);
+            }
+        }
+    }
+}
+
+/// Runs callbacks from the EL1 physical timer interrupt. Every core has its own timer, so every
+/// core gets its own queue and callbacks run on the core that scheduled them
+pub struct TimerQueue {
+    queues: [IRQSafeLock<TimerQueueInner>; NUM_CORES],
+    /// Timer PPI once registered, zero before. Enabled separately on every core
+    irq: AtomicUsize,
+}
+
+#[allow(dead_code)]
+impl TimerQueue {
+    pub const fn new() -> Self {
+        Self {
+            queues: [const { IRQSafeLock::new(TimerQueueInner::new()) }; NUM_CORES],
+            irq: AtomicUsize::new(0),
+        }
+    }
+
+    fn add(
+        &self,
+        deadline: TimerValue,
+        period: Option<TimerValue>,
+        callback: fn(),
+    ) -> Result<TimerHandle, &'static str> {
+        let core = cpu::id();
+        self.queues[core].lock(|i| i.add(core, deadline, period, callback))
+    }
+
+    /// Enable the timer interrupt on a secondary core
+    pub fn init_current_core(&self) {
+        CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::CLEAR + CNTP_CTL_EL0::IMASK::SET);
+
+        let irq = self.irq.load(Ordering::Acquire);
+        if irq != 0 {
+            crate::driver::gic().enable(irq);
+            self.queues[cpu::id()].lock(|i| i.ready = true);
+        }
+    }
+
+    /// Run `callback` once, `after` from now
+    pub fn schedule_once(
+        &self,
+        after: Duration,
+        callback: fn(),
+    ) -> Result<TimerHandle, &'static str> {
+        let delta: TimerValue = after.try_into()?;
+        let deadline = current_cntpct() + delta;
+
+        self.add(deadline, None, callback)
+    }
+
+    /// Run `callback` every `period`, starting one period from now
+    pub fn schedule_periodic(
+        &self,
+        period: Duration,
+        callback: fn(),
+    ) -> Result<TimerHandle, &'static str> {
+        let period: TimerValue = period.try_into()?;
+        let deadline = current_cntpct() + period;
+
+        self.add(deadline, Some(period), callback)
+    }
+
+    /// Arm a compare event for `deadline` without any work attached, to wake up a core waiting
+    /// in `wfi`
+    pub(super) fn wake_at(&self, deadline: TimerValue) -> Result<TimerHandle, &'static str> {
+        let core = cpu::id();
+        self.queues[core].lock(|i| {
+            if !i.ready {
+                return Err("Timer interrupt not registered");
+            }
+            i.add(core, deadline, None, || {})
+        })
+    }
+
+    /// Returns false if the timer already fired or was cancelled
+    pub fn cancel(&self, handle: TimerHandle) -> bool {
+        self.queues[handle.core].lock(|i| i.cancel(handle))
+    }
+}
+
+impl Driver for TimerQueue {
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::CLEAR + CNTP_CTL_EL0::IMASK::SET);
+        Ok(())
+    }
+
+    fn register_irq_handler(&'static self, irq: IRQNumber) -> Result<(), &'static str> {
+        let gic = crate::driver::gic();
+        gic.register_handler(IRQHandlerDescriptor {
+            number: irq,
+            name: "Timer",
+            handler: self,
+        })?;
+
+        // Only reaches the boot core, the others enable it in `init_current_core`
+        gic.enable(irq);
+        self.irq.store(irq, Ordering::Release);
+        self.queues[cpu::id()].lock(|i| i.ready = true);
+        Ok(())
+    }
+}
+
+impl IRQHandler for TimerQueue {
+    fn handle(&self) -> Result<(), &'static str> {
+        let now = current_cntpct();
+
+        // Callbacks run without the lock held so they can schedule new timers
+        let expired = self.queues[cpu::id()].lock(|i| i.expire(now));
+        for cb in expired.into_iter().flatten() {
+            cb();
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+//! Fills the `.ksyms` section of a linked kernel ELF with a table of its function symbols, so
+//! the kernel can print `function+offset` instead of raw addresses.
+//!
+//! Layout, all little endian:
+//!   magic "KSYM", u32 symbol count
+//!   count * { u64 address, u32 size, u32 name offset, u32 name length, u32 padding }
+//!   names, not NUL terminated. Offsets are relative to the start of the section
+//!
+//! Entries are sorted by address.
+
+use std::{env, fs, process::ExitCode};
+
+const SECTION_NAME: &str = ".ksyms";
+const MAGIC: &[u8; 4] = b"KSYM";
+const HEADER_SIZE: usize = 8;
+const ENTRY_SIZE: usize = 24;
+
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+
+struct Section {
+    name: u32,
+    kind: u32,
+    offset: usize,
+    size: usize,
+    link: u32,
+}
+
+struct Symbol {
+    addr: u64,
+    size: u64,
+    name: String,
+}
+
+fn u16_at(b: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes(b[off..off + 2].try_into().unwrap())
+}
+
+fn u32_at(b: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(b[off..off + 4].try_into().unwrap())
+}
+
+fn u64_at(b: &[u8], off: usize) -> u64 {
+    u64::from_le_bytes(b[off..off + 8].try_into().unwrap())
+}
+
+fn str_at(b: &[u8], off: usize) -> &str {
+    let end = b[off..]
+        .iter()
+        .position(|c| *c == 0)
+        .unwrap_or(b.len() - off);
+    std::str::from_utf8(&b[off..off + end]).unwrap_or("")
+}
+
+fn sections(elf: &[u8]) -> Result<Vec<Section>, String> {
+    if elf.len() < 64 || &elf[0..4] != b"\x7FELF" || elf[4] != 2 || elf[5] != 1 {
+        return Err("not a little endian ELF64 file".into());
+    }
+
+    let shoff = u64_at(elf, 0x28) as usize;
+    let shentsize = u16_at(elf, 0x3A) as usize;
+    let shnum = u16_at(elf, 0x3C) as usize;
+
+    (0..shnum)
+        .map(|i| {
+            let off = shoff + i * shentsize;
+            if off + shentsize > elf.len() {
+                return Err("section header out of bounds".to_string());
+            }
+            Ok(Section {
+                name: u32_at(elf, off),
+                kind: u32_at(elf, off + 4),
+                offset: u64_at(elf, off + 0x18) as usize,
+                size: u64_at(elf, off + 0x20) as usize,
+                link: u32_at(elf, off + 0x28),
+            })
+        })
+        .collect()
+}
+
+fn symbols(elf: &[u8], sections: &[Section]) -> Result<Vec<Symbol>, String> {
+    let symtab = sections
+        .iter()
+        .find(|s| s.kind == SHT_SYMTAB)
+        .ok_or("no symbol table, was the kernel stripped?")?;
+    let strtab = &sections[symtab.link as usize];
+    let strings = &elf[strtab.offset..strtab.offset + strtab.size];
+
+    let mut syms: Vec<Symbol> = elf[symtab.offset..symtab.offset + symtab.size]
+        .chunks_exact(24)
+        .filter(|s| s[4] & 0xF == STT_FUNC)
+        .map(|s| Symbol {
+            addr: u64_at(s, 8),
+            size: u64_at(s, 16),
+            name: format!(
+                "{:#}",
+                rustc_demangle::demangle(str_at(strings, u32_at(s, 0) as usize))
+            ),
+        })
+        .filter(|s| s.addr != 0)
+        .collect();
+
+    syms.sort_by_key(|s| s.addr);
+    syms.dedup_by_key(|s| s.addr);
+    Ok(syms)
+}
+
+fn serialize(syms: &[Symbol], capacity: usize) -> Result<Vec<u8>, String> {
+    let mut table = Vec::with_capacity(capacity);
+    table.extend_from_slice(MAGIC);
+    table.extend_from_slice(&(syms.len() as u32).to_le_bytes());
+
+    let mut name_off = HEADER_SIZE + syms.len() * ENTRY_SIZE;
+    for s in syms {
+        table.extend_from_slice(&s.addr.to_le_bytes());
+        table.extend_from_slice(&(s.size.min(u32::MAX as u64) as u32).to_le_bytes());
+        table.extend_from_slice(&(name_off as u32).to_le_bytes());
+        table.extend_from_slice(&(s.name.len() as u32).to_le_bytes());
+        table.extend_from_slice(&0u32.to_le_bytes());
+        name_off += s.name.len();
+    }
+    for s in syms {
+        table.extend_from_slice(s.name.as_bytes());
+    }
+
+    if table.len() > capacity {
+        return Err(format!(
+            "symbol table needs {} bytes but {} only has {}",
+            table.len(),
+            SECTION_NAME,
+            capacity
+        ));
+    }
+    table.resize(capacity, 0);
+    Ok(table)
+}
+
+fn run(path: &str) -> Result<(), String> {
+    let mut elf = fs::read(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+
+    let sections = sections(&elf)?;
+    let shstrndx = u16_at(&elf, 0x3E) as usize;
+    let shstrtab = sections
+        .get(shstrndx)
+        .ok_or("bad section name table index")?;
+    let ksyms = sections
+        .iter()
+        .find(|s| str_at(&elf, shstrtab.offset + s.name as usize) == SECTION_NAME)
+        .ok_or(format!("no {} section", SECTION_NAME))?;
+
+    let syms = symbols(&elf, &sections)?;
+    let table = serialize(&syms, ksyms.size)?;
+    elf[ksyms.offset..ksyms.offset + ksyms.size].copy_from_slice(&table);
+
+    fs::write(path, elf).map_err(|e| format!("failed to write {}: {}", path, e))?;
+    println!("Embedded {} symbols into {}", syms.len(), path);
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 2 {
+        eprintln!("usage: {} <kernel elf>", args[0]);
+        return ExitCode::FAILURE;
+    }
+
+    match run(&args[1]) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("ksyms: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
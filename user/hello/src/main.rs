@@ -0,0 +1,49 @@
+//! Test program for the kernel's ELF loader. Runs at EL0 and only talks to the kernel through
+//! system calls, the numbers have to match `syscall::nr` in the kernel
+
+#![no_std]
+#![no_main]
+
+use core::{arch::asm, panic::PanicInfo};
+
+const SYS_WRITE: u64 = 0;
+const SYS_EXIT: u64 = 1;
+
+unsafe fn syscall(nr: u64, a0: u64, a1: u64) -> u64 {
+    let ret;
+    asm!("svc #0", inout("x0") a0 => ret, in("x1") a1, in("x8") nr, options(nostack));
+    ret
+}
+
+fn write(s: &str) {
+    unsafe { syscall(SYS_WRITE, s.as_ptr() as u64, s.len() as u64) };
+}
+
+fn exit(code: u64) -> ! {
+    unsafe { syscall(SYS_EXIT, code, 0) };
+    loop {
+        core::hint::spin_loop()
+    }
+}
+
+static mut COUNTER: u64 = 0;
+
+#[no_mangle]
+#[link_section = ".text.start"]
+pub extern "C" fn _start() -> ! {
+    write("Hello from an ELF program\n");
+
+    // Touch the data segment to check it is writable
+    let count = unsafe {
+        let counter = &raw mut COUNTER;
+        *counter += 41;
+        *counter + 1
+    };
+
+    exit(count)
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    exit(u64::MAX)
+}